@@ -0,0 +1,69 @@
+//! Prints the metrics table of the README, generated from `DISH_METRICS`.
+//!
+//!     cargo run --example readme_table
+
+use prometheus::core::Collector;
+use starlink_exporter::metrics::{Kind, Metrics, DISH_METRICS};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let metrics = Metrics::new()?;
+
+    // metrics not derived from the status response come first
+    let mut rows = vec![];
+    for (collector, type_name, unit) in [
+        (&metrics.up as &dyn Collector, "Gauge", ""),
+        (&metrics.reboots, "Counter", ""),
+        (&metrics.last_reboot_time, "Gauge", "seconds"),
+    ] {
+        let desc = collector.desc()[0];
+        rows.push([
+            format!("`starlink_{}`", desc.fq_name),
+            type_name.to_string(),
+            unit.to_string(),
+            desc.help.clone(),
+        ]);
+    }
+    for def in DISH_METRICS {
+        rows.push([
+            format!("`starlink_dish_{}`", def.name),
+            def.type_name().to_string(),
+            def.unit.unwrap_or_default().to_string(),
+            def.help.to_string(),
+        ]);
+
+        if let Kind::Counter(_) = def.kind {
+            rows.push([
+                format!("`starlink_dish_{}_created`", def.name),
+                "Gauge".to_string(),
+                "seconds".to_string(),
+                format!("Unix time the count of `{}` started at.", def.name),
+            ]);
+        }
+    }
+
+    let header = ["Name", "Type", "Unit", "Description"].map(str::to_string);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    print_row(&header, &widths);
+    print_row(&widths.map(|w| "-".repeat(w)), &widths);
+    for row in &rows {
+        print_row(row, &widths);
+    }
+
+    Ok(())
+}
+
+fn print_row(row: &[String; 4], widths: &[usize; 4]) {
+    let cells = row
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| format!("{:width$}", cell, width = width))
+        .collect::<Vec<_>>();
+
+    println!("| {} |", cells.join(" | "));
+}
@@ -0,0 +1,178 @@
+use starlink_exporter::dish::Dish;
+use std::{future, net::SocketAddr};
+use tracing::{error, info};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::{auth, exporter_error::Error, exporter_metrics::ExporterMetrics};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Reboot,
+    Stow,
+    Unstow,
+}
+
+impl Action {
+    /// Name of the action as used in the path of its endpoint and in the `action` label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Reboot => "reboot",
+            Action::Stow => "stow",
+            Action::Unstow => "unstow",
+        }
+    }
+}
+
+/// Performs actions on the dish, auditing each of them.
+#[derive(Debug, Clone)]
+pub struct Admin {
+    dish: Dish,
+    exporter_metrics: ExporterMetrics,
+}
+
+impl Admin {
+    pub fn new(dish: Dish, exporter_metrics: ExporterMetrics) -> Self { Admin { dish, exporter_metrics } }
+
+    /// Performs `action` on behalf of `origin`, e.g. the address of a client, logging the outcome and counting it if
+    /// the dish accepted it.
+    pub async fn perform(&self, action: Action, origin: &str) -> Result<(), Error> {
+        let result = match action {
+            Action::Reboot => self.dish.reboot().await,
+            Action::Stow => self.dish.stow().await,
+            Action::Unstow => self.dish.unstow().await,
+        }
+        .map_err(Error::from);
+
+        match &result {
+            Ok(()) => {
+                info!(action = action.as_str(), origin, "performed admin action");
+                self.exporter_metrics
+                    .admin_actions_total
+                    .with_label_values(&[action.as_str()])
+                    .inc();
+            },
+            Err(e) => {
+                error!(action = action.as_str(), origin, error = %e, "failed admin action");
+                self.exporter_metrics.observe_error(e);
+            },
+        }
+
+        result
+    }
+}
+
+/// `POST /admin/reboot`, `POST /admin/stow` and `POST /admin/unstow`. Require the admin `token` as bearer token,
+/// independent of the credentials of the web config. Without a token, the routes don't exist.
+pub fn routes(admin: Admin, token: Option<String>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let enabled = token.is_some();
+
+    warp::path("admin")
+        .and_then(move || {
+            future::ready(match enabled {
+                true => Ok(()),
+                false => Err(warp::reject::not_found()),
+            })
+        })
+        .untuple_one()
+        .and(auth::bearer_token(token.unwrap_or_default()))
+        .and(
+            route(admin.clone(), Action::Reboot)
+                .or(route(admin.clone(), Action::Stow))
+                .or(route(admin, Action::Unstow)),
+        )
+}
+
+fn route(admin: Admin, action: Action) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::post()
+        .and(warp::path(action.as_str()))
+        .and(warp::path::end())
+        .and(warp::addr::remote())
+        .and_then(move |addr: Option<SocketAddr>| {
+            let admin = admin.clone();
+
+            async move {
+                let origin = addr.map(|a| a.to_string()).unwrap_or_else(|| "-".to_string());
+
+                let reply = match admin.perform(action, &origin).await {
+                    Ok(()) => warp::reply::with_status("OK".to_string(), StatusCode::OK),
+                    Err(e) => warp::reply::with_status(format!("{}: {}", action.as_str(), e), StatusCode::BAD_GATEWAY),
+                };
+
+                Ok(reply) as Result<_, Rejection>
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlink::proto::space_x::api::device::{request, response, RebootResponse, Request};
+    use tonic::transport::Channel;
+
+    use crate::mock_dish;
+
+    const TOKEN: &str = "secret";
+
+    async fn mock_dish() -> (Dish, mock_dish::Requests) {
+        mock_dish::serve(|_| response::Response::Reboot(RebootResponse {})).await
+    }
+
+    fn unconnected_dish() -> Dish { Dish::from_channel(Channel::from_static("http://127.0.0.1:9200").connect_lazy()) }
+
+    async fn post(
+        admin: Admin,
+        token: Option<&str>,
+        authorization: &str,
+    ) -> warp::http::Response<warp::hyper::body::Bytes> {
+        let routes = routes(admin, token.map(str::to_string)).recover(auth::recover);
+
+        warp::test::request()
+            .method("POST")
+            .path("/admin/reboot")
+            .header("authorization", authorization)
+            .reply(&routes)
+            .await
+    }
+
+    #[tokio::test]
+    async fn not_found_without_token() {
+        let admin = Admin::new(unconnected_dish(), ExporterMetrics::new().unwrap());
+
+        let response = post(admin, None, &format!("Bearer {}", TOKEN)).await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn unauthorized_with_wrong_token() {
+        let (dish, requests) = mock_dish().await;
+        let admin = Admin::new(dish, ExporterMetrics::new().unwrap());
+
+        let response = post(admin, Some(TOKEN), "Bearer wrong").await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reboots_dish() {
+        let (dish, requests) = mock_dish().await;
+        let exporter_metrics = ExporterMetrics::new().unwrap();
+        let admin = Admin::new(dish, exporter_metrics.clone());
+
+        let response = post(admin, Some(TOKEN), &format!("Bearer {}", TOKEN)).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(matches!(requests.lock().unwrap().as_slice(), [Request {
+            request: Some(request::Request::Reboot(_)),
+            ..
+        }]));
+        assert_eq!(
+            exporter_metrics
+                .admin_actions_total
+                .with_label_values(&["reboot"])
+                .get(),
+            1_f64
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, Mutex},
+};
+use tracing::info;
+use warp::{
+    http::{header::WWW_AUTHENTICATE, StatusCode},
+    reject::Reject,
+    Filter,
+    Rejection,
+    Reply,
+};
+
+use crate::web_config::WebConfig;
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+#[derive(Debug)]
+struct Credentials {
+    basic_auth_users: HashMap<String, String>,
+    bearer_tokens: Vec<String>,
+    /// Hash verified against for unknown users, so response times don't reveal which users exist.
+    dummy_hash: String,
+    /// Users mapped to a keyed hash of the last password verified for them, so only the first request with a password
+    /// pays for its bcrypt check.
+    verified: Mutex<HashMap<String, u64>>,
+    hasher: RandomState,
+}
+
+/// Requires a valid `Authorization` header if the web config has any basic auth users or bearer tokens configured.
+/// Rejections are turned into `401 Unauthorized` responses by `recover`.
+pub fn filter(web_config: &WebConfig) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let credentials = match web_config.basic_auth_users.is_empty() && web_config.bearer_tokens.is_empty() {
+        true => None,
+        false => Some(Arc::new(Credentials {
+            basic_auth_users: web_config.basic_auth_users.clone(),
+            bearer_tokens: web_config.bearer_tokens.clone(),
+            dummy_hash: bcrypt::hash("", bcrypt::DEFAULT_COST).expect("hashing dummy password"),
+            verified: Mutex::new(HashMap::new()),
+            hasher: RandomState::new(),
+        })),
+    };
+
+    warp::header::optional::<String>("authorization")
+        .and_then(move |authorization: Option<String>| {
+            let credentials = credentials.clone();
+
+            async move {
+                let credentials = match credentials {
+                    Some(credentials) => credentials,
+                    None => return Ok(()),
+                };
+
+                let authorized = match authorization {
+                    Some(authorization) if credentials.verified_before(&authorization) => true,
+                    Some(authorization) => tokio::task::spawn_blocking(move || credentials.verify(&authorization))
+                        .await
+                        .unwrap_or(false),
+                    None => false,
+                };
+
+                match authorized {
+                    true => Ok(()),
+                    false => {
+                        info!("rejecting unauthorized request");
+
+                        Err(warp::reject::custom(Unauthorized))
+                    },
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Requires an `Authorization: Bearer` header with `token`, independent of the credentials of the web config.
+pub fn bearer_token(token: String) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let token = Arc::new(token);
+
+    warp::header::optional::<String>("authorization")
+        .and_then(move |authorization: Option<String>| {
+            let token = token.clone();
+
+            async move {
+                match authorization.as_deref().and_then(|a| a.strip_prefix("Bearer ")) {
+                    Some(t) if constant_time_eq(token.as_bytes(), t.trim().as_bytes()) => Ok(()),
+                    _ => {
+                        info!("rejecting unauthorized request");
+
+                        Err(warp::reject::custom(Unauthorized))
+                    },
+                }
+            }
+        })
+        .untuple_one()
+}
+
+pub async fn recover(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    match rejection.find::<Unauthorized>() {
+        Some(Unauthorized) => Ok(warp::reply::with_header(
+            warp::reply::with_status("Unauthorized", StatusCode::UNAUTHORIZED),
+            WWW_AUTHENTICATE,
+            "Basic",
+        )),
+        None => Err(rejection),
+    }
+}
+
+impl Credentials {
+    fn verify(&self, authorization: &str) -> bool {
+        if let Some(token) = authorization.strip_prefix("Bearer ") {
+            return self.bearer_tokens.iter().fold(false, |valid, t| {
+                valid | constant_time_eq(t.as_bytes(), token.trim().as_bytes())
+            });
+        }
+
+        let (user, password) = match basic_credentials(authorization) {
+            Some(credentials) => credentials,
+            None => return false,
+        };
+
+        match self.basic_auth_users.get(&user) {
+            Some(hash) => {
+                let valid = bcrypt::verify(&password, hash).unwrap_or(false);
+                if valid {
+                    let key = self.key(&user, &password);
+                    self.verified.lock().unwrap().insert(user, key);
+                }
+
+                valid
+            },
+            None => {
+                let _ = bcrypt::verify(&password, &self.dummy_hash);
+                false
+            },
+        }
+    }
+
+    /// Whether `authorization` carries basic auth credentials that were verified before, without checking them again.
+    fn verified_before(&self, authorization: &str) -> bool {
+        match basic_credentials(authorization) {
+            Some((user, password)) => self.verified.lock().unwrap().get(&user) == Some(&self.key(&user, &password)),
+            None => false,
+        }
+    }
+
+    fn key(&self, user: &str, password: &str) -> u64 {
+        // users can't contain colons
+        let mut hasher = self.hasher.build_hasher();
+        hasher.write(user.as_bytes());
+        hasher.write(b":");
+        hasher.write(password.as_bytes());
+        hasher.finish()
+    }
+}
+
+/// User and password of an `Authorization: Basic` header.
+fn basic_credentials(authorization: &str) -> Option<(String, String)> {
+    let encoded = authorization.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(base64::decode(encoded.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+
+    Some((user.to_string(), password.to_string()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials() -> Credentials {
+        Credentials {
+            basic_auth_users: IntoIterator::into_iter([("prometheus".to_string(), bcrypt::hash("secret", 4).unwrap())])
+                .collect(),
+            bearer_tokens: vec!["token".to_string()],
+            dummy_hash: bcrypt::hash("", 4).unwrap(),
+            verified: Mutex::new(HashMap::new()),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn basic(user: &str, password: &str) -> String {
+        format!("Basic {}", base64::encode(format!("{}:{}", user, password)))
+    }
+
+    #[test]
+    fn verifies_credentials() {
+        let credentials = credentials();
+
+        assert!(credentials.verify("Bearer token"));
+        assert!(!credentials.verify("Bearer other"));
+        assert!(credentials.verify(&basic("prometheus", "secret")));
+        assert!(!credentials.verify(&basic("prometheus", "other")));
+        assert!(!credentials.verify(&basic("other", "secret")));
+        assert!(!credentials.verify("Basic invalid"));
+    }
+
+    #[test]
+    fn caches_verified_credentials() {
+        let credentials = credentials();
+
+        assert!(!credentials.verified_before(&basic("prometheus", "secret")));
+        assert!(credentials.verify(&basic("prometheus", "secret")));
+        assert!(credentials.verified_before(&basic("prometheus", "secret")));
+
+        // failed checks aren't cached
+        assert!(!credentials.verify(&basic("prometheus", "other")));
+        assert!(!credentials.verified_before(&basic("prometheus", "other")));
+        assert!(!credentials.verify(&basic("other", "secret")));
+        assert!(!credentials.verified_before(&basic("other", "secret")));
+    }
+}
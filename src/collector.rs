@@ -0,0 +1,169 @@
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    Registry,
+};
+use std::collections::HashMap;
+use tokio::{
+    runtime::{self, Handle, RuntimeFlavor},
+    sync::Mutex,
+    task,
+};
+use tonic::transport::Channel;
+use tracing::{error, info};
+
+use crate::{dish::Dish, error::Error, metrics::Metrics};
+
+/// Collector polling the dish on every `collect`. Metrics are named and labeled like those of the exporter, i.e.
+/// prefixed with `starlink_` and labeled with the `id` and `hardware_version` of the dish.
+///
+/// `collect` blocks until the poll finished. The connection to the dish is driven by the runtime the collector was
+/// created on, which has to be the multi-threaded one. `collect` may be called outside of a runtime or within the
+/// multi-threaded one, but can't block a `current_thread` runtime: called on one, it logs an error and returns the
+/// values of the previous poll with `starlink_up` set to `0`.
+#[derive(Debug)]
+pub struct StarlinkCollector {
+    dish: Dish,
+    metrics: Mutex<Metrics>,
+    registry: Registry,
+    descs: Vec<Desc>,
+}
+
+impl StarlinkCollector {
+    pub async fn connect(address: String) -> Result<Self, Error> { Self::new(Dish::connect(address).await?).await }
+
+    pub async fn from_channel(channel: Channel) -> Result<Self, Error> { Self::new(Dish::from_channel(channel)).await }
+
+    /// Fails on a `current_thread` runtime, see above.
+    pub async fn new(dish: Dish) -> Result<Self, Error> {
+        if Handle::current().runtime_flavor() == RuntimeFlavor::CurrentThread {
+            return Err(Error::Config(
+                "StarlinkCollector requires the multi-threaded Tokio runtime".to_string(),
+            ));
+        }
+
+        let mut labels = HashMap::new();
+
+        if let Some(device_info) = dish.get_device_info().await? {
+            if let Some(id) = device_info.id {
+                info!("setting collector label id = {}", &id);
+                labels.insert("id".to_string(), id);
+            }
+            if let Some(hardware_version) = device_info.hardware_version {
+                info!("setting collector label hardware_version = {}", &hardware_version);
+                labels.insert("hardware_version".to_string(), hardware_version);
+            }
+        }
+
+        let metrics = Metrics::new()?;
+
+        let registry = Registry::new_custom(Some("starlink".to_string()), Some(labels.clone()))?;
+        metrics.register(&registry, &[])?;
+
+        // the registry prefixes and labels the metrics on gathering, so their descriptors have to be adjusted alike
+        let mut descs = vec![];
+        for collector in metrics.collectors(&[]) {
+            for desc in collector.desc() {
+                let mut const_labels = labels.clone();
+                const_labels.extend(
+                    desc.const_label_pairs
+                        .iter()
+                        .map(|l| (l.get_name().to_string(), l.get_value().to_string())),
+                );
+
+                descs.push(Desc::new(
+                    format!("starlink_{}", desc.fq_name),
+                    desc.help.clone(),
+                    desc.variable_labels.clone(),
+                    const_labels,
+                )?);
+            }
+        }
+
+        Ok(StarlinkCollector {
+            dish,
+            metrics: Mutex::new(metrics),
+            registry,
+            descs,
+        })
+    }
+
+    async fn poll(&self) -> Result<(), Error> {
+        let mut metrics = self.metrics.lock().await;
+
+        match metrics.update(&self.dish).await {
+            Ok(_) => {
+                metrics.up.set(1_f64);
+                Ok(())
+            },
+            Err(e) => {
+                metrics.up.set(0_f64);
+                Err(e)
+            },
+        }
+    }
+}
+
+impl Collector for StarlinkCollector {
+    fn desc(&self) -> Vec<&Desc> { self.descs.iter().collect() }
+
+    /// Polls the dish, falling back to the values of the previous poll with `starlink_up` set to `0` if that fails.
+    fn collect(&self) -> Vec<MetricFamily> {
+        let result = match Handle::try_current() {
+            // `block_in_place` panics on a `current_thread` runtime
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::CurrentThread => {
+                if let Ok(metrics) = self.metrics.try_lock() {
+                    metrics.up.set(0_f64);
+                }
+
+                Err(Error::Config(
+                    "StarlinkCollector can't poll within a current_thread runtime".to_string(),
+                ))
+            },
+            Ok(handle) => task::block_in_place(|| handle.block_on(self.poll())),
+            Err(_) => runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(Error::from)
+                .and_then(|runtime| runtime.block_on(self.poll())),
+        };
+        if let Err(e) = result {
+            error!("polling Starlink device: {}", e);
+        }
+
+        self.registry.gather()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rejects_current_thread_runtime() {
+        let channel = Channel::from_static("http://127.0.0.1:9200").connect_lazy();
+
+        let result = StarlinkCollector::from_channel(channel).await;
+
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn collects_on_current_thread_runtime() {
+        let runtime = runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+        let collector = runtime.block_on(async {
+            StarlinkCollector {
+                dish: Dish::from_channel(Channel::from_static("http://127.0.0.1:9200").connect_lazy()),
+                metrics: Mutex::new(Metrics::new().unwrap()),
+                registry: Registry::new(),
+                descs: vec![],
+            }
+        });
+        collector.metrics.try_lock().unwrap().up.set(1_f64);
+
+        let current_thread = runtime::Builder::new_current_thread().build().unwrap();
+        current_thread.block_on(async { collector.collect() });
+
+        assert_eq!(collector.metrics.try_lock().unwrap().up.get(), 0_f64);
+    }
+}
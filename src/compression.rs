@@ -0,0 +1,124 @@
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// Picks the encoding to respond with from an `Accept-Encoding` header, preferring the coding with the higher
+    /// quality and gzip over deflate on ties. Codings with `q=0` are treated as not acceptable, and `*` only applies to
+    /// codings not listed themselves.
+    pub fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let mut gzip = None;
+        let mut deflate = None;
+        let mut any = None;
+
+        for coding in accept_encoding.split(',') {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default().to_ascii_lowercase();
+            let q = params
+                .find_map(|p| p.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1_f32);
+
+            match name.as_str() {
+                "gzip" | "x-gzip" => gzip = Some(q),
+                "deflate" => deflate = Some(q),
+                "*" => any = Some(q),
+                _ => {},
+            }
+        }
+
+        let gzip = gzip.or(any).unwrap_or_default();
+        let deflate = deflate.or(any).unwrap_or_default();
+        match (gzip > 0_f32, deflate > 0_f32) {
+            (true, true) if deflate > gzip => Some(Encoding::Deflate),
+            (true, _) => Some(Encoding::Gzip),
+            (false, true) => Some(Encoding::Deflate),
+            _ => None,
+        }
+    }
+
+    /// Value of the `Content-Encoding` header.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            },
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use std::io::Read;
+
+    #[test]
+    fn negotiates_encoding() {
+        for (accept_encoding, encoding) in [
+            ("", None),
+            ("identity", None),
+            ("br", None),
+            ("gzip", Some(Encoding::Gzip)),
+            ("GZIP", Some(Encoding::Gzip)),
+            ("x-gzip", Some(Encoding::Gzip)),
+            ("deflate", Some(Encoding::Deflate)),
+            ("*", Some(Encoding::Gzip)),
+            // gzip is preferred on ties
+            ("deflate, gzip", Some(Encoding::Gzip)),
+            ("br;q=1.0, deflate;q=0.8, gzip;q=0.8", Some(Encoding::Gzip)),
+            ("gzip;q=0.5, deflate", Some(Encoding::Deflate)),
+            ("gzip;q=0.5, deflate;q=0.4", Some(Encoding::Gzip)),
+            ("gzip;q=0", None),
+            ("gzip; q=0.0, deflate", Some(Encoding::Deflate)),
+            ("gzip;q=0, deflate;q=0", None),
+            ("gzip;q=invalid", Some(Encoding::Gzip)),
+            // `*` doesn't override codings listed themselves
+            ("gzip;q=0, *", Some(Encoding::Deflate)),
+            ("*;q=0, deflate", Some(Encoding::Deflate)),
+            ("*;q=0.5, gzip;q=0.2", Some(Encoding::Deflate)),
+            ("*;q=0", None),
+        ] {
+            assert_eq!(Encoding::negotiate(accept_encoding), encoding, "{}", accept_encoding);
+        }
+    }
+
+    #[test]
+    fn compresses() {
+        let data = b"starlink_up 1\n".repeat(100);
+
+        let mut decompressed = vec![];
+        GzDecoder::new(&Encoding::Gzip.compress(&data).unwrap()[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+
+        let mut decompressed = vec![];
+        ZlibDecoder::new(&Encoding::Deflate.compress(&data).unwrap()[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+}
@@ -0,0 +1,304 @@
+use starlink_exporter::metrics::Group;
+use std::{collections::HashMap, path::PathBuf, str::FromStr, time::Duration};
+use tracing::Level;
+
+use crate::{
+    exporter_error::Error,
+    link_probes::LinkProbe,
+    listener::BindAddress,
+    logging::LogFormat,
+    schedule::Schedule,
+    storage::Retention,
+    summaries::Window,
+    watchdog::{Condition, Rule},
+};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind_address: BindAddress,
+    pub starlink_address: String,
+    pub poll_interval: Option<Duration>,
+    pub ready_max_age: Option<Duration>,
+    pub web_config_file: Option<PathBuf>,
+    pub metrics_include: Vec<String>,
+    pub metrics_exclude: Vec<String>,
+    pub disabled_metric_groups: Vec<Group>,
+    pub const_labels: HashMap<String, String>,
+    /// Name of the label carrying the dish ID. `None` if the ID is hidden.
+    pub id_label: Option<String>,
+    pub compression_min_bytes: usize,
+    /// Deadline of requests to the dish, unless a shorter one is derived from the scrape timeout.
+    pub request_timeout: Duration,
+    /// Subtracted from the scrape timeout sent by Prometheus, leaving time to encode the response.
+    pub scrape_timeout_offset: Duration,
+    pub shutdown_grace_period: Duration,
+    pub log_level: Level,
+    pub log_format: LogFormat,
+    pub log_reboots: bool,
+    /// Bearer token required by the admin endpoints. `None` disables them.
+    pub admin_token: Option<String>,
+    pub stow_schedule: Option<Schedule>,
+    pub unstow_schedule: Option<Schedule>,
+    /// Rules of the watchdog. The watchdog is disabled if empty.
+    pub watchdog_rules: Vec<Rule>,
+    pub watchdog_max_reboots_per_day: usize,
+    pub watchdog_dry_run: bool,
+    /// Hosts to ping from the dish. Pings are disabled if empty.
+    pub probe_ping_targets: Vec<String>,
+    pub probe_ping_interval: Duration,
+    pub probe_speed_test_schedule: Option<Schedule>,
+    /// Probes run by the exporter itself. Disabled if empty.
+    pub link_probes: Vec<LinkProbe>,
+    pub link_probe_interval: Duration,
+    pub link_probe_timeout: Duration,
+    /// Path of the SQLite database. Storage is disabled if `None`.
+    pub storage_path: Option<PathBuf>,
+    pub storage_retention: Retention,
+    /// Whether the per-second history of the dish is stored as well.
+    pub storage_history: bool,
+    /// Directory of the CSV export files. Export is disabled if `None`.
+    pub export_dir: Option<PathBuf>,
+    /// Age of export files to delete. Files are kept if `None`.
+    pub export_retention: Option<Duration>,
+    /// Whether files of finished days are converted to Parquet. Requires the `parquet` feature.
+    pub export_parquet: bool,
+    pub sla_metrics: bool,
+    /// Whether the SLA metrics are computed from the per-second history of the dish, or from polled state only.
+    pub sla_history: bool,
+    /// Windows of the latency and drop rate summaries. Summaries are disabled if empty.
+    pub summary_windows: Vec<Window>,
+    /// Whether the summaries are computed from the per-second history of the dish, or from polled values only.
+    pub summary_history: bool,
+    /// Path of the state file of the obstruction trend. The trend is disabled if `None`.
+    pub obstruction_trend_path: Option<PathBuf>,
+    /// Change of a wedge from its baseline raising an event.
+    pub obstruction_trend_threshold: f64,
+}
+
+/// Label names set by the exporter itself, which can't be used for constant labels. Prometheus rejects the whole scrape
+/// if a series carries a label twice, so this has to include the variable labels of every metric.
+const RESERVED_LABELS: &[&str] = &[
+    // dish metrics
+    "hardware_version",
+    "software_version",
+    "country_code",
+    "wedge",
+    // exporter metrics
+    "version",
+    "rustc",
+    "git_sha",
+    "request",
+    "result",
+    "path",
+    "status",
+    "kind",
+    "action",
+    "rule",
+    // probes
+    "target",
+    "protocol",
+    // SLA and summaries
+    "window",
+    "state",
+    "quantile",
+    // histograms
+    "le",
+];
+
+impl Config {
+    pub fn from_env() -> Result<Self, Error> {
+        let poll_interval = var("POLL_INTERVAL_MS")?.map(Duration::from_millis);
+
+        let id_label = match var::<bool>("HIDE_ID_LABEL")?.unwrap_or(false) {
+            true => None,
+            false => Some(label_name(
+                "ID_LABEL",
+                var("ID_LABEL")?.unwrap_or_else(|| "id".to_string()),
+            )?),
+        };
+
+        let mut const_labels = HashMap::new();
+        for label in list::<String>("CONST_LABELS")? {
+            let (name, value) = label
+                .split_once('=')
+                .ok_or_else(|| Error::Config(format!("parsing CONST_LABELS entry {}", label)))?;
+            let name = label_name("CONST_LABELS", name.trim().to_string())?;
+            if id_label.as_ref() == Some(&name) {
+                return Err(Error::Config(format!(
+                    "CONST_LABELS label {} conflicts with ID_LABEL",
+                    name
+                )));
+            }
+            const_labels.insert(name, value.trim().to_string());
+        }
+
+        let mut watchdog_rules = vec![];
+        for (condition, name) in [
+            (Condition::NotConnected, "WATCHDOG_NOT_CONNECTED_S"),
+            (Condition::PingDropRate, "WATCHDOG_PING_DROP_RATE_S"),
+            (Condition::ThermalShutdown, "WATCHDOG_THERMAL_SHUTDOWN_S"),
+        ] {
+            if let Some(duration) = var(name)? {
+                watchdog_rules.push(Rule {
+                    condition,
+                    duration: Duration::from_secs(duration),
+                });
+            }
+        }
+        // without background polling, rules would only be evaluated on scrapes, and not at all once they stop
+        if !watchdog_rules.is_empty() && poll_interval.is_none() {
+            return Err(Error::Config("watchdog rules require POLL_INTERVAL_MS".to_string()));
+        }
+
+        let export_parquet = var("EXPORT_PARQUET")?.unwrap_or(false);
+        if export_parquet && !cfg!(feature = "parquet") {
+            return Err(Error::Config(
+                "EXPORT_PARQUET requires building with the parquet feature".to_string(),
+            ));
+        }
+
+        let summary_windows = match var::<bool>("SUMMARIES")?.unwrap_or(false) {
+            true => match list("SUMMARY_WINDOWS")? {
+                windows if windows.is_empty() => vec!["5m".parse()?, "1h".parse()?, "24h".parse()?],
+                windows => windows,
+            },
+            false => vec![],
+        };
+
+        let obstruction_trend_threshold = var("OBSTRUCTION_TREND_THRESHOLD")?.unwrap_or(0.05);
+        if !(obstruction_trend_threshold > 0_f64 && obstruction_trend_threshold <= 1_f64) {
+            return Err(Error::Config(
+                "OBSTRUCTION_TREND_THRESHOLD must be greater than 0 and at most 1".to_string(),
+            ));
+        }
+
+        let config = Config {
+            bind_address: var("BIND_ADDRESS")?.unwrap_or_else(|| BindAddress::Tcp(([0, 0, 0, 0], 9184).into())),
+            starlink_address: var("STARLINK_ADDRESS")?.unwrap_or_else(|| "http://dishy.starlink.com:9200".to_string()),
+            poll_interval,
+            // with background polling, default to allowing two missed polls
+            ready_max_age: var("READY_MAX_AGE_S")?
+                .map(Duration::from_secs)
+                .or_else(|| poll_interval.map(|i| i * 3)),
+            web_config_file: var("WEB_CONFIG_FILE")?,
+            metrics_include: list("METRICS_INCLUDE")?,
+            metrics_exclude: list("METRICS_EXCLUDE")?,
+            disabled_metric_groups: list("DISABLED_METRIC_GROUPS")?,
+            const_labels,
+            id_label,
+            compression_min_bytes: var("COMPRESSION_MIN_BYTES")?.unwrap_or(1024),
+            request_timeout: Duration::from_millis(var("REQUEST_TIMEOUT_MS")?.unwrap_or(10_000)),
+            scrape_timeout_offset: Duration::from_millis(var("SCRAPE_TIMEOUT_OFFSET_MS")?.unwrap_or(500)),
+            shutdown_grace_period: Duration::from_secs(var("SHUTDOWN_GRACE_PERIOD_S")?.unwrap_or(10)),
+            log_level: var("LOG_LEVEL")?.unwrap_or(Level::INFO),
+            log_format: var("LOG_FORMAT")?.unwrap_or(LogFormat::Text),
+            log_reboots: var("LOG_REBOOTS")?.unwrap_or(true),
+            admin_token: var("ADMIN_TOKEN")?,
+            stow_schedule: var("STOW_SCHEDULE")?,
+            unstow_schedule: var("UNSTOW_SCHEDULE")?,
+            watchdog_rules,
+            watchdog_max_reboots_per_day: var("WATCHDOG_MAX_REBOOTS_PER_DAY")?.unwrap_or(1),
+            watchdog_dry_run: var("WATCHDOG_DRY_RUN")?.unwrap_or(false),
+            probe_ping_targets: list("PROBE_PING_TARGETS")?,
+            probe_ping_interval: Duration::from_secs(var("PROBE_PING_INTERVAL_S")?.unwrap_or(60)),
+            probe_speed_test_schedule: var("PROBE_SPEED_TEST_SCHEDULE")?,
+            link_probes: list("LINK_PROBES")?,
+            link_probe_interval: Duration::from_secs(var("LINK_PROBE_INTERVAL_S")?.unwrap_or(60)),
+            link_probe_timeout: Duration::from_millis(var("LINK_PROBE_TIMEOUT_MS")?.unwrap_or(5_000)),
+            storage_path: var("STORAGE_PATH")?,
+            storage_retention: Retention {
+                raw: Duration::from_secs(var("STORAGE_RAW_RETENTION_H")?.unwrap_or(48) * 60 * 60),
+                downsampled: Duration::from_secs(var("STORAGE_RETENTION_D")?.unwrap_or(90) * 24 * 60 * 60),
+                step: Duration::from_secs(var("STORAGE_DOWNSAMPLE_S")?.unwrap_or(300)),
+            },
+            storage_history: var("STORAGE_HISTORY")?.unwrap_or(true),
+            export_dir: var("EXPORT_DIR")?,
+            export_retention: var("EXPORT_RETENTION_D")?.map(|d: u64| Duration::from_secs(d * 24 * 60 * 60)),
+            export_parquet,
+            sla_metrics: var("SLA_METRICS")?.unwrap_or(false),
+            sla_history: var("SLA_HISTORY")?.unwrap_or(true),
+            summary_windows,
+            summary_history: var("SUMMARY_HISTORY")?.unwrap_or(true),
+            obstruction_trend_path: var("OBSTRUCTION_TREND_PATH")?,
+            obstruction_trend_threshold,
+        };
+
+        Ok(config)
+    }
+}
+
+/// Reads and parses an optional env var, treating empty values as unset.
+fn var<T: FromStr>(name: &str) -> Result<Option<T>, Error> {
+    match dotenv::var(name) {
+        Ok(value) if !value.is_empty() => value
+            .parse::<T>()
+            .map(Some)
+            .map_err(|_| Error::Config(format!("parsing {}", name))),
+        _ => Ok(None),
+    }
+}
+
+/// Reads and parses an optional comma-separated list env var.
+fn list<T: FromStr>(name: &str) -> Result<Vec<T>, Error> {
+    match dotenv::var(name) {
+        Ok(value) => value
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|v| {
+                v.parse::<T>()
+                    .map_err(|_| Error::Config(format!("parsing {} entry {}", name, v)))
+            })
+            .collect(),
+        Err(_) => Ok(vec![]),
+    }
+}
+
+/// Validates a label name against the Prometheus data model and the labels used by the exporter.
+fn label_name(var: &str, name: String) -> Result<String, Error> {
+    let mut chars = name.chars();
+    let valid = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__");
+
+    match valid && !RESERVED_LABELS.contains(&name.as_str()) {
+        true => Ok(name),
+        false => Err(Error::Config(format!(
+            "invalid or reserved label name {} in {}",
+            name, var
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, sync::Mutex};
+
+    /// Held by tests setting env vars, as tests run concurrently.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn rejects_reserved_const_labels() {
+        let _env = ENV.lock().unwrap();
+        env::set_var("CONST_LABELS", "status=x");
+        let result = Config::from_env();
+        env::remove_var("CONST_LABELS");
+
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn requires_poll_interval_for_watchdog() {
+        let _env = ENV.lock().unwrap();
+        env::set_var("WATCHDOG_NOT_CONNECTED_S", "600");
+        let result = Config::from_env();
+        env::set_var("POLL_INTERVAL_MS", "1000");
+        let with_poll_interval = Config::from_env();
+        env::remove_var("WATCHDOG_NOT_CONNECTED_S");
+        env::remove_var("POLL_INTERVAL_MS");
+
+        assert!(matches!(result, Err(Error::Config(e)) if e.contains("POLL_INTERVAL_MS")));
+        assert_eq!(with_poll_interval.unwrap().watchdog_rules.len(), 1);
+    }
+}
@@ -0,0 +1,172 @@
+use prometheus::HistogramVec;
+use std::time::Duration;
+use tonic::transport::Channel;
+use tracing::{debug, trace};
+
+use crate::error::Error;
+use starlink::proto::space_x::api::device::{
+    device_client::DeviceClient,
+    request,
+    response,
+    DeviceInfo,
+    DishGetHistoryResponse,
+    DishGetStatusResponse,
+    DishStowRequest,
+    GetDeviceInfoRequest,
+    GetHistoryRequest,
+    GetStatusRequest,
+    PingHostRequest,
+    PingResult,
+    RebootRequest,
+    Request,
+    SpeedTestRequest,
+    SpeedTestResponse,
+};
+
+/// gRPC client of the Starlink dish. Clones share the same channel.
+#[derive(Debug, Clone)]
+pub struct Dish {
+    address: Option<String>,
+    client: DeviceClient<Channel>,
+    request_duration: Option<HistogramVec>,
+    timeout: Option<Duration>,
+}
+
+impl Dish {
+    pub async fn connect(address: String) -> Result<Self, Error> {
+        let client = DeviceClient::connect(address.clone()).await?;
+
+        Ok(Dish {
+            address: Some(address),
+            client,
+            request_duration: None,
+            timeout: None,
+        })
+    }
+
+    /// Uses an existing channel to the dish, e.g. one with custom timeouts or shared with other clients.
+    pub fn from_channel(channel: Channel) -> Self {
+        Dish {
+            address: None,
+            client: DeviceClient::new(channel),
+            request_duration: None,
+            timeout: None,
+        }
+    }
+
+    /// Observes the duration of every request in `request_duration`, labeled by the request type.
+    pub fn with_request_duration(mut self, request_duration: HistogramVec) -> Self {
+        self.request_duration = Some(request_duration);
+        self
+    }
+
+    /// Sets a deadline on every request, after which it fails with `DEADLINE_EXCEEDED`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Address the dish was connected to. `None` if created from a channel.
+    pub fn address(&self) -> Option<&str> { self.address.as_deref() }
+
+    pub async fn handle(&self, request: request::Request) -> Result<Option<response::Response>, Error> {
+        let timer = self
+            .request_duration
+            .as_ref()
+            .map(|h| h.with_label_values(&[request_name(&request)]).start_timer());
+
+        debug!("sending {} request to Starlink device", request_name(&request));
+        let mut req = tonic::Request::new(Request {
+            request: Some(request),
+            ..Default::default()
+        });
+        if let Some(timeout) = self.timeout {
+            req.set_timeout(timeout);
+        }
+        let res = self.client.clone().handle(req).await?;
+        trace!("received gRPC response: {:#?}", &res);
+
+        if let Some(timer) = timer {
+            timer.observe_duration();
+        }
+
+        Ok(res.into_inner().response)
+    }
+
+    pub async fn get_device_info(&self) -> Result<Option<DeviceInfo>, Error> {
+        match self
+            .handle(request::Request::GetDeviceInfo(GetDeviceInfoRequest {}))
+            .await?
+        {
+            Some(response::Response::GetDeviceInfo(r)) => Ok(r.device_info),
+            _ => Ok(None),
+        }
+    }
+
+    pub async fn get_status(&self) -> Result<Option<DishGetStatusResponse>, Error> {
+        match self.handle(request::Request::GetStatus(GetStatusRequest {})).await? {
+            Some(response::Response::DishGetStatus(r)) => Ok(Some(r)),
+            _ => Ok(None),
+        }
+    }
+
+    pub async fn get_history(&self) -> Result<Option<DishGetHistoryResponse>, Error> {
+        match self.handle(request::Request::GetHistory(GetHistoryRequest {})).await? {
+            Some(response::Response::DishGetHistory(r)) => Ok(Some(r)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Pings `address` from the dish.
+    pub async fn ping_host(&self, address: String) -> Result<Option<PingResult>, Error> {
+        match self
+            .handle(request::Request::PingHost(PingHostRequest { address: Some(address) }))
+            .await?
+        {
+            Some(response::Response::PingHost(r)) => Ok(r.result),
+            _ => Ok(None),
+        }
+    }
+
+    /// Runs a speed test from the dish, which takes a while.
+    pub async fn speed_test(&self) -> Result<Option<SpeedTestResponse>, Error> {
+        match self.handle(request::Request::SpeedTest(SpeedTestRequest {})).await? {
+            Some(response::Response::SpeedTest(r)) => Ok(Some(r)),
+            _ => Ok(None),
+        }
+    }
+
+    pub async fn reboot(&self) -> Result<(), Error> {
+        self.handle(request::Request::Reboot(RebootRequest {})).await?;
+
+        Ok(())
+    }
+
+    pub async fn stow(&self) -> Result<(), Error> {
+        self.handle(request::Request::DishStow(DishStowRequest { unstow: Some(false) }))
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn unstow(&self) -> Result<(), Error> {
+        self.handle(request::Request::DishStow(DishStowRequest { unstow: Some(true) }))
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Name of the request type as used in the `request` label.
+fn request_name(request: &request::Request) -> &'static str {
+    match request {
+        request::Request::GetDeviceInfo(_) => "get_device_info",
+        request::Request::GetStatus(_) => "get_status",
+        request::Request::GetHistory(_) => "get_history",
+        request::Request::Reboot(_) => "reboot",
+        request::Request::DishStow(_) => "dish_stow",
+        request::Request::PingHost(_) => "ping_host",
+        request::Request::SpeedTest(_) => "speed_test",
+        _ => "other",
+    }
+}
@@ -1,16 +1,34 @@
 use thiserror::Error;
-use warp::reject::Reject;
 
+/// Errors of talking to the dish and of its metrics. The exporter wraps these in its own error type.
 #[derive(Debug, Error)]
 pub enum Error {
+    /// Boxed, as `tonic::Status` is large enough to make every `Result` carrying the error that large.
     #[error("Tonic Status Error")]
-    TonicStatus(#[from] tonic::Status),
+    TonicStatus(#[source] Box<tonic::Status>),
     #[error("Tonic Status Error")]
     TonicTransport(#[from] tonic::transport::Error),
     #[error("Prometheus Error")]
     Prometheus(#[from] prometheus::Error),
-    #[error("HTTP Error")]
-    Http(#[from] warp::http::Error),
+    #[error("IO Error")]
+    Io(#[from] std::io::Error),
+    #[error("Configuration Error: {0}")]
+    Config(String),
 }
 
-impl Reject for Error {}
+impl Error {
+    /// Name of the variant as used in the `kind` label of the error counter.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::TonicStatus(_) => "tonic_status",
+            Error::TonicTransport(_) => "tonic_transport",
+            Error::Prometheus(_) => "prometheus",
+            Error::Io(_) => "io",
+            Error::Config(_) => "config",
+        }
+    }
+}
+
+impl From<tonic::Status> for Error {
+    fn from(e: tonic::Status) -> Self { Error::TonicStatus(Box::new(e)) }
+}
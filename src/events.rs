@@ -0,0 +1,179 @@
+use serde::Serialize;
+use starlink_exporter::status::{State, Status};
+
+use crate::history::HistorySample;
+
+/// Events published on every poll of the dish and pushed to stream subscribers.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Status(Status),
+    StateChanged {
+        from: Option<State>,
+        to: State,
+    },
+    AlertRaised {
+        alert: &'static str,
+    },
+    AlertCleared {
+        alert: &'static str,
+    },
+    ObstructionStarted,
+    ObstructionStopped,
+    Rebooted {
+        previous_uptime_s: Option<u64>,
+        uptime_s: Option<u64>,
+    },
+    /// Published instead of `Status` when polling the dish failed.
+    PollFailed {
+        error: String,
+    },
+    /// Samples of the history of the dish added since the previous poll, following its `Status` if the poller reads
+    /// the history. Not streamed to subscribers.
+    History(Vec<HistorySample>),
+    /// Published by `ObstructionTrend` rather than derived from consecutive polls.
+    ObstructionIncreased {
+        wedge: usize,
+        baseline: f64,
+        fraction: f64,
+    },
+}
+
+impl Event {
+    /// Name of the event as sent in the `event` field of SSE messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Status(_) => "status",
+            Event::History(_) => "history",
+            Event::StateChanged { .. } => "state_changed",
+            Event::AlertRaised { .. } => "alert_raised",
+            Event::AlertCleared { .. } => "alert_cleared",
+            Event::ObstructionStarted => "obstruction_started",
+            Event::ObstructionStopped => "obstruction_stopped",
+            Event::Rebooted { .. } => "rebooted",
+            Event::PollFailed { .. } => "poll_failed",
+            Event::ObstructionIncreased { .. } => "obstruction_increased",
+        }
+    }
+}
+
+/// Derives the discrete transition events between two consecutive polls. Fields missing from either response don't
+/// produce events.
+pub fn transitions(previous: Option<&Status>, current: &Status) -> Vec<Event> {
+    let mut events = vec![];
+
+    if let Some(to) = current.state {
+        let from = previous.and_then(|p| p.state);
+        if from != Some(to) {
+            events.push(Event::StateChanged { from, to });
+        }
+    }
+
+    if let Some(previous) = previous {
+        if current.rebooted_since(previous) {
+            events.push(Event::Rebooted {
+                previous_uptime_s: previous.uptime_s,
+                uptime_s: current.uptime_s,
+            });
+        }
+
+        for ((alert, was_active), (_, is_active)) in previous.alerts.iter().zip(current.alerts.iter()) {
+            match (was_active, is_active) {
+                (Some(false), Some(true)) => events.push(Event::AlertRaised { alert }),
+                (Some(true), Some(false)) => events.push(Event::AlertCleared { alert }),
+                _ => {},
+            }
+        }
+
+        match (
+            previous.obstruction.currently_obstructed,
+            current.obstruction.currently_obstructed,
+        ) {
+            (Some(false), Some(true)) => events.push(Event::ObstructionStarted),
+            (Some(true), Some(false)) => events.push(Event::ObstructionStopped),
+            _ => {},
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlink_exporter::status::{Alerts, Obstruction};
+
+    fn status(state: State, uptime_s: u64, thermal_throttle: bool, obstructed: bool) -> Status {
+        Status {
+            state: Some(state),
+            uptime_s: Some(uptime_s),
+            alerts: Alerts {
+                thermal_throttle: Some(thermal_throttle),
+                ..Default::default()
+            },
+            obstruction: Obstruction {
+                currently_obstructed: Some(obstructed),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn names(events: &[Event]) -> Vec<&'static str> { events.iter().map(Event::name).collect() }
+
+    #[test]
+    fn publishes_state_of_first_poll() {
+        let events = transitions(None, &status(State::Connected, 10, true, true));
+
+        // alerts and obstruction need a previous poll
+        assert!(matches!(&events[..], [Event::StateChanged {
+            from: None,
+            to: State::Connected
+        }]));
+    }
+
+    #[test]
+    fn derives_transitions() {
+        let previous = status(State::Connected, 10, false, false);
+
+        assert!(transitions(Some(&previous), &status(State::Connected, 11, false, false)).is_empty());
+
+        let events = transitions(Some(&previous), &status(State::Searching, 11, true, true));
+        assert_eq!(names(&events), ["state_changed", "alert_raised", "obstruction_started"]);
+        assert!(matches!(events[0], Event::StateChanged {
+            from: Some(State::Connected),
+            to: State::Searching
+        }));
+        assert!(matches!(events[1], Event::AlertRaised {
+            alert: "thermal_throttle"
+        }));
+
+        let current = status(State::Connected, 11, false, false);
+        let events = transitions(Some(&status(State::Connected, 10, true, true)), &current);
+        assert_eq!(names(&events), ["alert_cleared", "obstruction_stopped"]);
+    }
+
+    #[test]
+    fn publishes_reboots() {
+        let events = transitions(
+            Some(&status(State::Connected, 1000, false, false)),
+            &status(State::Booting, 5, false, false),
+        );
+
+        assert_eq!(names(&events), ["state_changed", "rebooted"]);
+        assert!(matches!(events[1], Event::Rebooted {
+            previous_uptime_s: Some(1000),
+            uptime_s: Some(5)
+        }));
+    }
+
+    #[test]
+    fn ignores_missing_fields() {
+        let previous = status(State::Connected, 10, false, false);
+
+        let events = transitions(Some(&previous), &Status::default());
+        assert!(events.is_empty());
+        let events = transitions(Some(&Status::default()), &previous);
+        assert_eq!(names(&events), ["state_changed"]);
+    }
+}
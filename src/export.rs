@@ -0,0 +1,666 @@
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, TimeZone, Utc};
+use futures_util::{Stream, StreamExt};
+use serde::Deserialize;
+use starlink_exporter::{
+    metrics::{Kind as MetricKind, MetricDef, DISH_METRICS},
+    status::Status,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    future,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::task;
+use tracing::{debug, error, info};
+use warp::{
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        Response,
+        StatusCode,
+    },
+    hyper::Body,
+    Filter,
+    Rejection,
+    Reply,
+};
+
+use crate::{exporter_error::Error, exporter_metrics::ExporterMetrics, storage::Storage};
+use starlink::proto::space_x::api::device::DishGetStatusResponse;
+
+/// Number of 30 degree wedges around the dish.
+const WEDGES: usize = 12;
+
+/// Maximum number of rows returned by `/export.csv`.
+const MAX_ROWS: usize = 86_400;
+
+/// Column of the export files, holding the values of a dish metric.
+struct Column {
+    name: String,
+    def: &'static MetricDef,
+    /// Index of the label of an info metric the column holds.
+    label: Option<usize>,
+    wedge: Option<usize>,
+}
+
+impl Column {
+    /// Whether the column holds the values of the stored series `name` with `labels`. Labels of info metrics are
+    /// read from the series labels instead.
+    fn matches(&self, name: &str, labels: &BTreeMap<String, String>) -> bool {
+        if name != self.def.name {
+            return false;
+        }
+
+        match (self.def.kind, self.label) {
+            (MetricKind::Info(names, _), Some(label)) => labels.contains_key(names[label]),
+            _ => labels.get("wedge").cloned() == self.wedge.map(|w| w.to_string()),
+        }
+    }
+
+    /// Value of the column in `response`, read like the dish metric. Empty where the dish didn't report it.
+    fn cell(&self, response: &DishGetStatusResponse) -> String {
+        let cell = match self.def.kind {
+            MetricKind::Gauge(value) | MetricKind::Counter(value) => value(response).map(format_value),
+            MetricKind::Wedges(values) => values(response)
+                .and_then(|values| values.get(self.wedge?))
+                .map(|v| v.to_string()),
+            MetricKind::Info(_, values) => values(response).and_then(|values| values.get(self.label?).cloned()),
+        };
+
+        cell.unwrap_or_default()
+    }
+}
+
+/// Writes the dish status of every poll as a row to a CSV file per UTC day in `dir`. Optionally converts the files
+/// of finished days to Parquet and deletes files older than `retention`.
+#[derive(Debug)]
+pub struct Exporter {
+    dir: PathBuf,
+    retention: Option<Duration>,
+    parquet: bool,
+    exporter_metrics: ExporterMetrics,
+}
+
+/// CSV file of the current day.
+struct DayFile {
+    date: NaiveDate,
+    writer: csv::Writer<File>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportQuery {
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+impl Exporter {
+    /// Creates `dir` if it doesn't exist yet.
+    pub fn new(
+        dir: PathBuf,
+        retention: Option<Duration>,
+        parquet: bool,
+        exporter_metrics: ExporterMetrics,
+    ) -> Result<Self, Error> {
+        fs::create_dir_all(&dir)?;
+
+        Ok(Exporter {
+            dir,
+            retention,
+            parquet,
+            exporter_metrics,
+        })
+    }
+
+    /// Writes a row for the status of every poll until shutdown. Files of finished days are converted and expired
+    /// files are deleted on startup and whenever the day changes.
+    pub async fn run(self, statuses: impl Stream<Item = Status>) {
+        let mut file: Option<DayFile> = None;
+        tokio::pin!(statuses);
+
+        if let Err(e) = self.finish_days(Utc::now().date_naive()).await {
+            error!("finishing export files: {}", e);
+            self.exporter_metrics.observe_error(&e);
+        }
+
+        while let Some(status) = statuses.next().await {
+            if let Err(e) = self.write(&mut file, Utc::now(), &status).await {
+                error!("exporting dish status: {}", e);
+                self.exporter_metrics.observe_error(&e);
+            }
+        }
+    }
+
+    async fn write(&self, file: &mut Option<DayFile>, time: DateTime<Utc>, status: &Status) -> Result<(), Error> {
+        let date = time.date_naive();
+
+        if !matches!(file, Some(file) if file.date == date) {
+            // the previous file is closed before it's converted
+            let rotated = file.take().is_some();
+            *file = Some(DayFile::open(&self.dir, date)?);
+
+            if rotated {
+                self.finish_days(date).await?;
+            }
+        }
+
+        if let Some(file) = file {
+            let mut record = vec![time.format("%Y-%m-%dT%H:%M:%SZ").to_string()];
+            record.extend(columns().iter().map(|column| column.cell(&status.response)));
+
+            file.writer.write_record(&record)?;
+            file.writer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Converts the files of days before `today` without Parquet file and deletes expired files.
+    async fn finish_days(&self, today: NaiveDate) -> Result<(), Error> {
+        let dir = self.dir.clone();
+        let retention = self.retention;
+        let parquet = self.parquet;
+
+        blocking(move || {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                let date = match file_date(&path) {
+                    Some(date) => date,
+                    None => continue,
+                };
+
+                if let Some(retention) = retention {
+                    let expired = ChronoDuration::from_std(retention)
+                        .ok()
+                        .and_then(|retention| today.checked_sub_signed(retention))
+                        .map(|cutoff| date < cutoff)
+                        .unwrap_or(false);
+                    if expired {
+                        info!("deleting expired export file {}", path.display());
+                        fs::remove_file(&path)?;
+                        continue;
+                    }
+                }
+
+                let converted = path.with_extension("parquet");
+                if parquet && date < today && path.extension() == Some("csv".as_ref()) && !converted.exists() {
+                    info!("converting export file {} to Parquet", path.display());
+                    write_parquet(&path, &converted)?;
+                }
+            }
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+impl DayFile {
+    fn open(dir: &Path, date: NaiveDate) -> Result<Self, Error> {
+        let path = dir.join(format!("starlink-{}.csv", date.format("%Y-%m-%d")));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let new = file.metadata()?.len() == 0;
+        debug!("opened export file {}", path.display());
+
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(file);
+        if new {
+            writer.write_record(std::iter::once("time").chain(columns().iter().map(|c| c.name.as_str())))?;
+        }
+
+        Ok(DayFile { date, writer })
+    }
+}
+
+/// `GET /export.csv?from=&to=`, returning the dish status stored between the Unix times `from` and `to` in the
+/// columns of the export files. `to` defaults to now and `from` to a day before `to`. Without storage, the route
+/// doesn't exist.
+pub fn route(storage: Option<Arc<Storage>>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("export.csv"))
+        .and_then(move || {
+            future::ready(match storage.clone() {
+                Some(storage) => Ok(storage),
+                None => Err(warp::reject::not_found()),
+            })
+        })
+        .and(warp::query::<ExportQuery>())
+        .and_then(|storage: Arc<Storage>, query: ExportQuery| async move {
+            let response = Response::builder();
+            let response = match range(&query, Utc::now().timestamp()) {
+                Err(message) => response.status(StatusCode::BAD_REQUEST).body(Body::from(message)),
+                Ok((from, to)) => match blocking(move || stored_csv(&storage, from, to)).await {
+                    Ok(None) => response.status(StatusCode::BAD_REQUEST).body(Body::from(format!(
+                        "more than {} rows, narrow the time range",
+                        MAX_ROWS
+                    ))),
+                    Ok(Some(csv)) => response
+                        .header(CONTENT_TYPE, "text/csv")
+                        .header(
+                            CONTENT_DISPOSITION,
+                            format!("attachment; filename=\"starlink-{}-{}.csv\"", from, to),
+                        )
+                        .body(Body::from(csv)),
+                    Err(e) => {
+                        error!("exporting stored dish status: {}", e);
+
+                        response
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(Body::from(e.to_string()))
+                    },
+                },
+            };
+
+            response.map_err(|e| warp::reject::custom(Error::from(e)))
+        })
+}
+
+/// Time range of `query`, or why it's invalid.
+fn range(query: &ExportQuery, now: i64) -> Result<(i64, i64), &'static str> {
+    let to = query.to.unwrap_or(now);
+    let from = match query.from {
+        Some(from) => from,
+        None => to.checked_sub(24 * 60 * 60).ok_or("invalid time range")?,
+    };
+
+    if from > to {
+        return Err("invalid time range");
+    }
+
+    Ok((from, to))
+}
+
+/// Exported columns, following the `time` column: one per dish metric, one per wedge of the wedge metrics and one per
+/// label of the info metrics.
+fn columns() -> Vec<Column> {
+    let mut columns = vec![];
+
+    for def in DISH_METRICS {
+        let column = |name: String, label: Option<usize>, wedge: Option<usize>| Column {
+            name,
+            def,
+            label,
+            wedge,
+        };
+
+        match def.kind {
+            MetricKind::Gauge(_) | MetricKind::Counter(_) => columns.push(column(def.name.to_string(), None, None)),
+            MetricKind::Wedges(_) =>
+                columns.extend((0..WEDGES).map(|i| column(format!("{}_{}", def.name, i), None, Some(i)))),
+            MetricKind::Info(labels, _) => columns.extend(
+                labels
+                    .iter()
+                    .enumerate()
+                    .map(|(i, label)| column(label.to_string(), Some(i), None)),
+            ),
+        }
+    }
+
+    columns
+}
+
+/// Formats `value` like the `f32` most fields of the dish are reported as, unless it doesn't fit into one.
+fn format_value(value: f64) -> String {
+    if value as f32 as f64 == value {
+        (value as f32).to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// CSV of the dish metrics in `storage` between `from` and `to`, with a row per stored time, or `None` if there are
+/// more than `MAX_ROWS` of them.
+fn stored_csv(storage: &Storage, from: i64, to: i64) -> Result<Option<Vec<u8>>, Error> {
+    let columns = columns();
+
+    // columns and, for info metrics, label values of every series
+    let mut series_columns: HashMap<i64, Vec<(usize, Option<String>)>> = HashMap::new();
+    for series in storage.series("starlink_dish_*")? {
+        let name = series.name.trim_start_matches("starlink_dish_");
+        let labels = &series.labels;
+
+        let targets = columns
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| column.matches(name, labels))
+            .map(|(i, column)| {
+                let label = match (column.def.kind, column.label) {
+                    (MetricKind::Info(names, _), Some(label)) => labels.get(names[label]).cloned(),
+                    _ => None,
+                };
+
+                (i, label)
+            })
+            .collect::<Vec<_>>();
+        if !targets.is_empty() {
+            series_columns.insert(series.id, targets);
+        }
+    }
+
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(std::iter::once("time").chain(columns.iter().map(|c| c.name.as_str())))?;
+
+    let mut row: Option<(i64, Vec<String>)> = None;
+    let mut rows = 0;
+    let mut result = Ok(());
+    storage.scan("starlink_dish_*", from, to, |timestamp, id, value| {
+        if !matches!(&row, Some((t, _)) if *t == timestamp) {
+            rows += 1;
+            if rows > MAX_ROWS {
+                return false;
+            }
+
+            if let Some((t, cells)) = row.take() {
+                result = write_row(&mut writer, t, &cells);
+                if result.is_err() {
+                    return false;
+                }
+            }
+            row = Some((timestamp, vec![String::new(); columns.len()]));
+        }
+
+        if let (Some((_, cells)), Some(targets)) = (&mut row, series_columns.get(&id)) {
+            for (i, label) in targets {
+                cells[*i] = match label {
+                    // only the current combination of labels is set to `1`
+                    Some(label) if value == 1_f64 => label.clone(),
+                    Some(_) => continue,
+                    None => format_value(value),
+                };
+            }
+        }
+
+        true
+    })?;
+    result?;
+
+    if rows > MAX_ROWS {
+        return Ok(None);
+    }
+    if let Some((t, cells)) = row {
+        write_row(&mut writer, t, &cells)?;
+    }
+
+    writer.into_inner().map(Some).map_err(|e| Error::from(e.into_error()))
+}
+
+fn write_row(writer: &mut csv::Writer<Vec<u8>>, timestamp: i64, cells: &[String]) -> Result<(), Error> {
+    let time = Utc
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .unwrap_or_default();
+
+    writer.write_record(std::iter::once(&time).chain(cells))?;
+
+    Ok(())
+}
+
+/// Date of an export file named `starlink-<date>.<extension>`.
+fn file_date(path: &Path) -> Option<NaiveDate> {
+    let stem = path.file_stem()?.to_str()?.strip_prefix("starlink-")?;
+
+    NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok()
+}
+
+/// Converts the CSV export file at `csv` to a Parquet file at `path`.
+#[cfg(feature = "parquet")]
+fn write_parquet(csv: &Path, path: &Path) -> Result<(), Error> {
+    use parquet::{
+        basic::Compression,
+        data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type},
+        file::{properties::WriterProperties, writer::SerializedFileWriter},
+        schema::parser::parse_message_type,
+    };
+
+    let columns = columns();
+
+    let mut times = vec![];
+    let mut values = vec![vec![]; columns.len()];
+    for record in csv::Reader::from_path(csv)?.into_records() {
+        let record = record?;
+        let time = match record.get(0).map(DateTime::parse_from_rfc3339) {
+            Some(Ok(time)) => time,
+            _ => continue,
+        };
+
+        times.push(time.timestamp_millis());
+        for (i, values) in values.iter_mut().enumerate() {
+            values.push(record.get(i + 1).filter(|v| !v.is_empty()).map(str::to_string));
+        }
+    }
+
+    let mut schema = "message starlink {\n  REQUIRED INT64 time (TIMESTAMP(MILLIS,true));\n".to_string();
+    for column in &columns {
+        // labels of info metrics are text, the values of all other metrics floats
+        schema += &match column.label {
+            Some(_) => format!("  OPTIONAL BYTE_ARRAY {} (UTF8);\n", column.name),
+            None => format!("  OPTIONAL DOUBLE {};\n", column.name),
+        };
+    }
+    schema += "}";
+
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let mut writer = SerializedFileWriter::new(
+        File::create(path)?,
+        Arc::new(parse_message_type(&schema)?),
+        Arc::new(properties),
+    )?;
+    let mut row_group = writer.next_row_group()?;
+
+    if let Some(mut column) = row_group.next_column()? {
+        column.typed::<Int64Type>().write_batch(&times, None, None)?;
+        column.close()?;
+    }
+    for (column_def, values) in columns.iter().zip(&values) {
+        let mut column = match row_group.next_column()? {
+            Some(column) => column,
+            None => break,
+        };
+        let levels = values.iter().map(|v| v.is_some() as i16).collect::<Vec<_>>();
+        let values = values.iter().flatten();
+
+        match column_def.label {
+            Some(_) => {
+                let values = values.map(|v| ByteArray::from(v.as_str())).collect::<Vec<_>>();
+                column
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            },
+            None => {
+                let values = values.map(|v| v.parse().unwrap_or_default()).collect::<Vec<f64>>();
+                column.typed::<DoubleType>().write_batch(&values, Some(&levels), None)?;
+            },
+        }
+        column.close()?;
+    }
+
+    row_group.close()?;
+    writer.close()?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(_: &Path, _: &Path) -> Result<(), Error> {
+    Err(Error::Config(
+        "Parquet export requires building with the parquet feature".to_string(),
+    ))
+}
+
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T, Error> + Send + 'static) -> Result<T, Error> {
+    task::spawn_blocking(f).await.map_err(io::Error::from)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlink::proto::space_x::api::device::{DeviceInfo, DeviceState, DishObstructionStats};
+
+    use crate::storage::Retention;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("starlink-exporter-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn storage(path: &Path) -> Storage {
+        let retention = Retention {
+            raw: Duration::from_secs(60),
+            downsampled: Duration::from_secs(60),
+            step: Duration::from_secs(60),
+        };
+
+        Storage::open(path, retention).unwrap()
+    }
+
+    #[test]
+    fn columns_follow_dish_metrics() {
+        let names = columns().into_iter().map(|c| c.name).collect::<Vec<_>>();
+
+        assert_eq!(&names[..4], [
+            "software_version",
+            "country_code",
+            "uptime_s",
+            "boot_time_seconds"
+        ]);
+        assert!(names.contains(&"obstruction_wedge_fraction_obstructed_11".to_string()));
+        for def in DISH_METRICS {
+            assert!(
+                names.iter().any(|name| name.starts_with(def.name)) || matches!(def.kind, MetricKind::Info(..)),
+                "{}",
+                def.name
+            );
+        }
+    }
+
+    #[test]
+    fn reads_cells_like_metrics() {
+        let response = DishGetStatusResponse {
+            device_info: Some(DeviceInfo {
+                software_version: Some("1.2.3".to_string()),
+                ..Default::default()
+            }),
+            device_state: Some(DeviceState { uptime_s: Some(86_400) }),
+            snr: Some(0.1),
+            stow_requested: Some(true),
+            obstruction_stats: Some(DishObstructionStats {
+                wedge_fraction_obstructed: vec![0.5],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let cells = columns()
+            .into_iter()
+            .map(|column| (column.name.clone(), column.cell(&response)))
+            .collect::<HashMap<_, _>>();
+
+        assert_eq!(cells["software_version"], "1.2.3");
+        assert_eq!(cells["country_code"], "");
+        assert_eq!(cells["uptime_s"], "86400");
+        assert_eq!(cells["snr"], "0.1");
+        assert_eq!(cells["stow_requested"], "1");
+        assert_eq!(cells["pop_ping_latency_ms"], "");
+        assert_eq!(cells["obstruction_wedge_fraction_obstructed_0"], "0.5");
+        assert_eq!(cells["obstruction_wedge_fraction_obstructed_1"], "");
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_ranges() {
+        let route = route(Some(Arc::new(storage(Path::new(":memory:")))));
+
+        for (query, status) in [
+            ("", StatusCode::OK),
+            ("?from=0&to=3600", StatusCode::OK),
+            ("?from=3600&to=0", StatusCode::BAD_REQUEST),
+            // overflowing
+            (&format!("?to={}", i64::MIN), StatusCode::BAD_REQUEST),
+        ] {
+            let response = warp::test::request()
+                .path(&format!("/export.csv{}", query))
+                .reply(&route)
+                .await;
+
+            assert_eq!(response.status(), status, "{}", query);
+        }
+    }
+
+    #[test]
+    fn limits_rows() {
+        let dir = temp_dir("limits_rows");
+        let path = dir.join("storage.db");
+        let storage = storage(&path);
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch(&format!(
+                "INSERT INTO series (id, name, labels) VALUES (1, 'starlink_dish_snr', '{{}}');
+                 WITH RECURSIVE t (timestamp) AS (SELECT 0 UNION ALL SELECT timestamp + 1 FROM t WHERE timestamp < {})
+                 INSERT INTO samples SELECT 1, timestamp, 9.0 FROM t;",
+                MAX_ROWS
+            ))
+            .unwrap();
+
+        assert!(stored_csv(&storage, 0, MAX_ROWS as i64).unwrap().is_none());
+        let csv = stored_csv(&storage, 1, MAX_ROWS as i64).unwrap().unwrap();
+        // and the header
+        assert_eq!(String::from_utf8(csv).unwrap().lines().count(), MAX_ROWS + 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "parquet")]
+    #[tokio::test]
+    async fn converts_to_parquet() {
+        use parquet::{
+            file::reader::{FileReader, SerializedFileReader},
+            record::RowAccessor,
+        };
+
+        const NOW: i64 = 1_700_000_000;
+
+        let dir = temp_dir("converts_to_parquet");
+        let exporter = Exporter::new(dir.clone(), None, true, ExporterMetrics::new().unwrap()).unwrap();
+        let response = DishGetStatusResponse {
+            device_info: Some(DeviceInfo {
+                software_version: Some("1.2.3".to_string()),
+                ..Default::default()
+            }),
+            snr: Some(9_f32),
+            ..Default::default()
+        };
+        let time = Utc.timestamp_opt(NOW, 0).unwrap();
+
+        let mut file = None;
+        exporter.write(&mut file, time, &Status::from(&response)).await.unwrap();
+        let empty = Status::from(&DishGetStatusResponse::default());
+        exporter
+            .write(&mut file, time + ChronoDuration::seconds(1), &empty)
+            .await
+            .unwrap();
+        drop(file);
+        exporter
+            .finish_days(time.date_naive().succ_opt().unwrap())
+            .await
+            .unwrap();
+
+        let path = dir.join(format!("starlink-{}.parquet", time.format("%Y-%m-%d")));
+        let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+        let rows = reader.get_row_iter(None).unwrap().collect::<Vec<_>>();
+        let columns = columns();
+        // following the time column
+        let index = |name: &str| columns.iter().position(|c| c.name == name).unwrap() + 1;
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get_timestamp_millis(0).unwrap(), NOW * 1000);
+        assert_eq!(rows[0].get_string(index("software_version")).unwrap(), "1.2.3");
+        assert_eq!(rows[0].get_double(index("snr")).unwrap(), 9_f64);
+        assert_eq!(rows[1].get_timestamp_millis(0).unwrap(), (NOW + 1) * 1000);
+        // not reported
+        assert!(rows[1].get_double(index("snr")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
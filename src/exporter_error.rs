@@ -0,0 +1,63 @@
+use thiserror::Error;
+use warp::reject::Reject;
+
+/// Errors of the exporter, adding those of the HTTP server, storage and export to the ones of the library.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Starlink(#[from] starlink_exporter::error::Error),
+    #[error("HTTP Error")]
+    Http(#[from] warp::http::Error),
+    #[error("YAML Error")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("TLS Error")]
+    Tls(#[from] tokio_rustls::rustls::Error),
+    #[error("Regex Error")]
+    Regex(#[from] regex::Error),
+    #[error("SQLite Error")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("CSV Error")]
+    Csv(#[from] csv::Error),
+    #[cfg(feature = "parquet")]
+    #[error("Parquet Error")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[error("Configuration Error: {0}")]
+    Config(String),
+}
+
+impl Error {
+    /// Name of the variant as used in the `kind` label of the error counter.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Starlink(e) => e.kind(),
+            Error::Http(_) => "http",
+            Error::Yaml(_) => "yaml",
+            Error::Tls(_) => "tls",
+            Error::Regex(_) => "regex",
+            Error::Sqlite(_) => "sqlite",
+            Error::Csv(_) => "csv",
+            #[cfg(feature = "parquet")]
+            Error::Parquet(_) => "parquet",
+            Error::Config(_) => "config",
+        }
+    }
+}
+
+// errors of the library's dependencies are kept in the library's variants
+impl From<tonic::Status> for Error {
+    fn from(e: tonic::Status) -> Self { Error::Starlink(e.into()) }
+}
+
+impl From<tonic::transport::Error> for Error {
+    fn from(e: tonic::transport::Error) -> Self { Error::Starlink(e.into()) }
+}
+
+impl From<prometheus::Error> for Error {
+    fn from(e: prometheus::Error) -> Self { Error::Starlink(e.into()) }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self { Error::Starlink(e.into()) }
+}
+
+impl Reject for Error {}
@@ -0,0 +1,94 @@
+use prometheus::{CounterVec, GaugeVec, HistogramOpts, HistogramVec, Opts, Registry};
+
+use crate::exporter_error::Error;
+
+/// Metrics about the exporter itself, registered without the dish labels.
+#[derive(Debug, Clone)]
+pub struct ExporterMetrics {
+    pub build_info: GaugeVec,
+
+    pub grpc_request_duration_seconds: HistogramVec,
+    pub polls_total: CounterVec,
+
+    pub http_requests_total: CounterVec,
+
+    pub errors_total: CounterVec,
+
+    pub admin_actions_total: CounterVec,
+    pub watchdog_triggers_total: CounterVec,
+}
+
+impl ExporterMetrics {
+    pub fn new() -> Result<Self, Error> {
+        let metrics = ExporterMetrics {
+            build_info: GaugeVec::new(
+                Opts::new(
+                    "build_info",
+                    "Build information. Exposing `version`, `rustc` and `git_sha` as labels.",
+                )
+                .namespace("starlink_exporter"),
+                &["version", "rustc", "git_sha"],
+            )?,
+
+            grpc_request_duration_seconds: HistogramVec::new(
+                HistogramOpts::new(
+                    "grpc_request_duration_seconds",
+                    "Duration of gRPC requests to the dish in seconds.",
+                )
+                .namespace("starlink_exporter"),
+                &["request"],
+            )?,
+            polls_total: CounterVec::new(
+                Opts::new("polls_total", "Polls of the dish status.").namespace("starlink_exporter"),
+                &["result"],
+            )?,
+
+            http_requests_total: CounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests served.").namespace("starlink_exporter"),
+                &["path", "status"],
+            )?,
+
+            errors_total: CounterVec::new(
+                Opts::new("errors_total", "Errors encountered, by kind.").namespace("starlink_exporter"),
+                &["kind"],
+            )?,
+
+            admin_actions_total: CounterVec::new(
+                Opts::new("admin_actions_total", "Admin actions performed on the dish.").namespace("starlink"),
+                &["action"],
+            )?,
+            watchdog_triggers_total: CounterVec::new(
+                Opts::new("watchdog_triggers_total", "Watchdog rules fired.").namespace("starlink"),
+                &["rule", "result"],
+            )?,
+        };
+
+        metrics
+            .build_info
+            .with_label_values(&[env!("CARGO_PKG_VERSION"), env!("RUSTC_VERSION"), env!("GIT_SHA")])
+            .set(1_f64);
+
+        Ok(metrics)
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<(), Error> {
+        registry.register(Box::new(self.build_info.clone()))?;
+
+        registry.register(Box::new(self.grpc_request_duration_seconds.clone()))?;
+        registry.register(Box::new(self.polls_total.clone()))?;
+
+        registry.register(Box::new(self.http_requests_total.clone()))?;
+
+        registry.register(Box::new(self.errors_total.clone()))?;
+
+        registry.register(Box::new(self.admin_actions_total.clone()))?;
+        registry.register(Box::new(self.watchdog_triggers_total.clone()))?;
+
+        #[cfg(target_os = "linux")]
+        registry.register(Box::new(prometheus::process_collector::ProcessCollector::for_self()))?;
+
+        Ok(())
+    }
+
+    pub fn observe_error(&self, error: &Error) { self.errors_total.with_label_values(&[error.kind()]).inc(); }
+}
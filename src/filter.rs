@@ -0,0 +1,120 @@
+use prometheus::proto::MetricFamily;
+use regex::Regex;
+
+use crate::exporter_error::Error;
+
+/// Include and exclude patterns applied to the names of gathered metric families before encoding. Patterns are globs
+/// supporting `*` and `?`, or regular expressions if enclosed in slashes, e.g. `/starlink_dish_alert_.+/`. Both have to
+/// match the whole name.
+#[derive(Debug, Clone, Default)]
+pub struct MetricFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl MetricFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, Error> {
+        Ok(MetricFilter {
+            include: include.iter().map(|p| pattern(p)).collect::<Result<_, _>>()?,
+            exclude: exclude.iter().map(|p| pattern(p)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Whether a metric family is exported. Without include patterns, everything not excluded is.
+    pub fn is_match(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|r| r.is_match(name)))
+            && !self.exclude.iter().any(|r| r.is_match(name))
+    }
+
+    pub fn apply(&self, mut metric_families: Vec<MetricFamily>) -> Vec<MetricFamily> {
+        metric_families.retain(|m| self.is_match(m.get_name()));
+        metric_families
+    }
+}
+
+fn pattern(pattern: &str) -> Result<Regex, Error> {
+    let regex = match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+        Some(regex) => regex.to_string(),
+        None => pattern
+            .split('*')
+            .map(|part| part.split('?').map(regex::escape).collect::<Vec<_>>().join("."))
+            .collect::<Vec<_>>()
+            .join(".*"),
+    };
+
+    Ok(Regex::new(&format!("^(?:{})$", regex))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Vec<String> { patterns.iter().map(|p| p.to_string()).collect() }
+
+    fn pattern_of(p: &str) -> Regex { pattern(p).unwrap() }
+
+    #[test]
+    fn matches_patterns() {
+        for (pattern, name, matches) in [
+            ("starlink_up", "starlink_up", true),
+            // the whole name has to match
+            ("starlink_up", "starlink_up_total", false),
+            ("starlink_up", "x_starlink_up", false),
+            ("starlink_dish_*", "starlink_dish_snr", true),
+            ("starlink_dish_*", "starlink_dish_", true),
+            ("starlink_dish_*", "starlink_up", false),
+            ("*_total", "starlink_polls_total", true),
+            ("*_total", "starlink_polls_total_created", false),
+            ("starlink_dish_?nr", "starlink_dish_snr", true),
+            ("starlink_dish_?nr", "starlink_dish_nr", false),
+            ("starlink_dish_?nr", "starlink_dish_ssnr", false),
+            // regex metacharacters of globs are literal
+            ("starlink.up", "starlink.up", true),
+            ("starlink.up", "starlink_up", false),
+            ("starlink_(up)", "starlink_(up)", true),
+            ("starlink_+", "starlink__", false),
+            ("starlink_[ab]", "starlink_a", false),
+            ("starlink_[ab]", "starlink_[ab]", true),
+            ("starlink_up|starlink_dish_snr", "starlink_up", false),
+            // regular expressions between slashes
+            ("/starlink_dish_alert_.+/", "starlink_dish_alert_motors_stuck", true),
+            ("/starlink_dish_alert_.+/", "starlink_dish_alert_", false),
+            ("/starlink_up|starlink_dish_snr/", "starlink_up", true),
+            ("/starlink_up|starlink_dish_snr/", "starlink_dish_snr", true),
+            ("/starlink_up|starlink_dish_snr/", "starlink_up_created", false),
+            ("/up/", "starlink_up", false),
+            ("/.*up/", "starlink_up", true),
+            // a single slash isn't a regex
+            ("/", "/", true),
+        ] {
+            assert_eq!(pattern_of(pattern).is_match(name), matches, "{} ~ {}", pattern, name);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_regex() {
+        assert!(pattern("/starlink_(/").is_err());
+        // but the same glob is literal
+        assert!(pattern("starlink_(").is_ok());
+    }
+
+    #[test]
+    fn includes_and_excludes() {
+        let filter = MetricFilter::new(
+            &patterns(&["starlink_dish_*", "starlink_up"]),
+            &patterns(&["starlink_dish_alert_*"]),
+        )
+        .unwrap();
+
+        assert!(filter.is_match("starlink_up"));
+        assert!(filter.is_match("starlink_dish_snr"));
+        assert!(!filter.is_match("starlink_dish_alert_motors_stuck"));
+        assert!(!filter.is_match("starlink_polls_total"));
+
+        // without includes, everything not excluded
+        let filter = MetricFilter::new(&[], &patterns(&["starlink_dish_*"])).unwrap();
+        assert!(filter.is_match("starlink_up"));
+        assert!(!filter.is_match("starlink_dish_snr"));
+        assert!(MetricFilter::default().is_match("starlink_up"));
+    }
+}
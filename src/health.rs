@@ -0,0 +1,48 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::poller::Poller;
+
+/// `GET /healthz`, reporting that the process is alive, and `GET /readyz`, reporting whether the last poll of the dish
+/// succeeded and, if `max_age` is set, happened no longer than `max_age` ago.
+pub fn routes(
+    poller: Arc<Poller>,
+    max_age: Option<Duration>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let healthz = warp::get()
+        .and(warp::path("healthz"))
+        .and(warp::path::end())
+        .map(|| "OK");
+
+    let readyz = warp::get()
+        .and(warp::path("readyz"))
+        .and(warp::path::end())
+        .map(move || {
+            let info = poller.info();
+
+            let (status, message) = if let Some(last_error) = info.last_error {
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    format!("last poll failed: {}", last_error),
+                )
+            } else {
+                match (max_age, info.last_success) {
+                    (Some(max_age), Some(last_success))
+                        if SystemTime::now().duration_since(last_success).unwrap_or_default() > max_age =>
+                        (
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            format!("last successful poll older than {:?}", max_age),
+                        ),
+                    (Some(_), None) => (StatusCode::SERVICE_UNAVAILABLE, "no successful poll yet".to_string()),
+                    _ => (StatusCode::OK, "OK".to_string()),
+                }
+            };
+
+            warp::reply::with_status(message, status)
+        });
+
+    healthz.or(readyz)
+}
@@ -0,0 +1,172 @@
+use serde::Serialize;
+use starlink::proto::space_x::api::device::DishGetHistoryResponse;
+
+/// Per-second sample of the history of the dish.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct HistorySample {
+    /// Unix time, derived from the time the history was read.
+    pub time: i64,
+    pub pop_ping_drop_rate: Option<f32>,
+    pub pop_ping_latency_ms: Option<f32>,
+    pub downlink_throughput_bps: Option<f32>,
+    pub uplink_throughput_bps: Option<f32>,
+    pub snr: Option<f32>,
+    pub scheduled: Option<bool>,
+    pub obstructed: Option<bool>,
+}
+
+/// Position in the ring buffers of the history of the dish, to take each sample only once across responses.
+#[derive(Debug, Default)]
+pub struct HistoryCursor {
+    /// `current` of the previous response.
+    current: Option<u64>,
+}
+
+impl HistoryCursor {
+    /// Samples added since the previous response, or all of them on the first response and after the dish rebooted.
+    /// The newest sample is taken at `now`, each one before a second earlier.
+    pub fn advance(&mut self, response: &DishGetHistoryResponse, now: i64) -> Vec<HistorySample> {
+        let current = match response.current {
+            Some(current) => current,
+            None => return vec![],
+        };
+        let added = match self.current {
+            Some(previous) if previous <= current => current - previous,
+            _ => current,
+        };
+        self.current = Some(current);
+
+        let len = [
+            response.pop_ping_drop_rate.len(),
+            response.pop_ping_latency_ms.len(),
+            response.downlink_throughput_bps.len(),
+            response.uplink_throughput_bps.len(),
+            response.snr.len(),
+            response.scheduled.len(),
+            response.obstructed.len(),
+        ]
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or_default() as u64;
+        if len == 0 {
+            return vec![];
+        }
+
+        // buffers shorter than the longest one lack the sample
+        let at = |n: u64| (n % len) as usize;
+        (current - added.min(len)..current)
+            .map(|n| HistorySample {
+                time: now - (current - 1 - n) as i64,
+                pop_ping_drop_rate: response.pop_ping_drop_rate.get(at(n)).copied(),
+                pop_ping_latency_ms: response.pop_ping_latency_ms.get(at(n)).copied(),
+                downlink_throughput_bps: response.downlink_throughput_bps.get(at(n)).copied(),
+                uplink_throughput_bps: response.uplink_throughput_bps.get(at(n)).copied(),
+                snr: response.snr.get(at(n)).copied(),
+                scheduled: response.scheduled.get(at(n)).copied(),
+                obstructed: response.obstructed.get(at(n)).copied(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    /// Response whose drop rate buffer holds the index of every slot, with `current` samples written to it.
+    fn response(len: usize, current: u64) -> DishGetHistoryResponse {
+        DishGetHistoryResponse {
+            current: Some(current),
+            pop_ping_drop_rate: (0..len).map(|n| n as f32).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn slots(samples: &[HistorySample]) -> Vec<(i64, Option<f32>)> {
+        samples.iter().map(|s| (s.time, s.pop_ping_drop_rate)).collect()
+    }
+
+    #[test]
+    fn takes_all_samples_first() {
+        let mut cursor = HistoryCursor::default();
+
+        let samples = cursor.advance(&response(4, 3), NOW);
+        assert_eq!(slots(&samples), [
+            (NOW - 2, Some(0_f32)),
+            (NOW - 1, Some(1_f32)),
+            (NOW, Some(2_f32))
+        ]);
+
+        // the whole buffer once it's full
+        let mut cursor = HistoryCursor::default();
+        assert_eq!(cursor.advance(&response(4, 10), NOW).len(), 4);
+    }
+
+    #[test]
+    fn wraps_around_ring_buffer() {
+        let mut cursor = HistoryCursor::default();
+        cursor.advance(&response(4, 3), NOW);
+
+        let samples = cursor.advance(&response(4, 6), NOW + 3);
+        assert_eq!(slots(&samples), [
+            (NOW + 1, Some(3_f32)),
+            (NOW + 2, Some(0_f32)),
+            (NOW + 3, Some(1_f32))
+        ]);
+
+        assert!(cursor.advance(&response(4, 6), NOW + 4).is_empty());
+
+        // samples overwritten since the previous response are lost
+        let samples = cursor.advance(&response(4, 16), NOW + 14);
+        assert_eq!(slots(&samples), [
+            (NOW + 11, Some(0_f32)),
+            (NOW + 12, Some(1_f32)),
+            (NOW + 13, Some(2_f32)),
+            (NOW + 14, Some(3_f32)),
+        ]);
+    }
+
+    #[test]
+    fn starts_over_after_reboot() {
+        let mut cursor = HistoryCursor::default();
+        cursor.advance(&response(4, 100), NOW);
+
+        let samples = cursor.advance(&response(4, 2), NOW + 5);
+        assert_eq!(slots(&samples), [(NOW + 4, Some(0_f32)), (NOW + 5, Some(1_f32))]);
+
+        let samples = cursor.advance(&response(4, 3), NOW + 6);
+        assert_eq!(slots(&samples), [(NOW + 6, Some(2_f32))]);
+    }
+
+    #[test]
+    fn lacks_samples_of_shorter_buffers() {
+        let mut cursor = HistoryCursor::default();
+        let response = DishGetHistoryResponse {
+            snr: vec![9_f32],
+            ..response(2, 2)
+        };
+
+        let samples = cursor.advance(&response, NOW);
+        assert_eq!(samples[0].snr, Some(9_f32));
+        assert_eq!(samples[1].snr, None);
+    }
+
+    #[test]
+    fn skips_responses_without_samples() {
+        let mut cursor = HistoryCursor::default();
+
+        assert!(cursor
+            .advance(
+                &DishGetHistoryResponse {
+                    current: None,
+                    ..response(4, 0)
+                },
+                NOW
+            )
+            .is_empty());
+        assert!(cursor.advance(&response(0, 3), NOW).is_empty());
+    }
+}
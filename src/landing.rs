@@ -0,0 +1,50 @@
+use std::{sync::Arc, time::SystemTime};
+use warp::{Filter, Rejection, Reply};
+
+use crate::poller::Poller;
+
+/// `GET /`, serving an HTML page linking to the metrics and showing the state of the exporter.
+pub fn route(poller: Arc<Poller>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::get().and(warp::path::end()).map(move || {
+        let info = poller.info();
+
+        warp::reply::html(format!(
+            r#"<!DOCTYPE html>
+<html>
+<head><title>Starlink Exporter</title></head>
+<body>
+<h1>Starlink Exporter</h1>
+<p><a href="/metrics">Metrics</a></p>
+<table>
+<tr><th align="left">Version</th><td>{}</td></tr>
+<tr><th align="left">Built with</th><td>{}</td></tr>
+<tr><th align="left">Git commit</th><td>{}</td></tr>
+<tr><th align="left">Dish address</th><td>{}</td></tr>
+<tr><th align="left">Last poll</th><td>{}</td></tr>
+<tr><th align="left">Last successful poll</th><td>{}</td></tr>
+<tr><th align="left">Last error</th><td>{}</td></tr>
+</table>
+</body>
+</html>
+"#,
+            env!("CARGO_PKG_VERSION"),
+            env!("RUSTC_VERSION"),
+            env!("GIT_SHA"),
+            escape(poller.starlink_address().unwrap_or("-")),
+            format_time(info.last_attempt),
+            format_time(info.last_success),
+            escape(info.last_error.as_deref().unwrap_or("-")),
+        ))
+    })
+}
+
+fn format_time(time: Option<SystemTime>) -> String {
+    time.map(httpdate::fmt_http_date).unwrap_or_else(|| "-".to_string())
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,12 @@
+//! Starlink dish metrics for Prometheus. [`StarlinkCollector`] exposes the metrics of the exporter as a
+//! `prometheus::core::Collector`, to register them in an existing registry instead of running the exporter.
+
+pub mod dish;
+pub mod error;
+pub mod metrics;
+pub mod status;
+pub mod util;
+
+mod collector;
+
+pub use crate::collector::StarlinkCollector;
@@ -0,0 +1,461 @@
+use hyper::{client::HttpConnector, Body, Client, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use prometheus::{Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, Opts, Registry};
+use starlink_exporter::util::unix_time_f64;
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use surge_ping::{PingIdentifier, PingSequence, ICMP};
+use tokio::{
+    net::{self, TcpStream, UdpSocket},
+    time::{self, MissedTickBehavior},
+};
+use tracing::debug;
+
+use crate::{exporter_error::Error, shutdown::Shutdown};
+
+type ProbeError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Probe run by the exporter itself, configured as `<protocol>:<target>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkProbe {
+    /// ICMP echo request to a host.
+    Icmp(String),
+    /// TCP connect to a `host:port`.
+    Tcp(String),
+    /// HTTP GET of a URL, succeeding on any response but client and server errors.
+    Http(Uri),
+    /// DNS resolution of a host name, with the resolver of the system or by querying a server for its A record.
+    Dns { host: String, server: Option<SocketAddr> },
+}
+
+impl FromStr for LinkProbe {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // URLs are HTTP probes without the protocol prefix as well
+        if s.starts_with("http://") || s.starts_with("https://") {
+            return Ok(LinkProbe::Http(parse_uri(s)?));
+        }
+
+        match s.split_once(':') {
+            Some(("icmp", host)) => Ok(LinkProbe::Icmp(host.to_string())),
+            Some(("tcp", address)) => Ok(LinkProbe::Tcp(address.to_string())),
+            Some(("http", url)) => Ok(LinkProbe::Http(parse_uri(url)?)),
+            Some(("dns", target)) => match target.split_once('@') {
+                Some((host, server)) => Ok(LinkProbe::Dns {
+                    host: host.to_string(),
+                    server: Some(parse_server(server)?),
+                }),
+                None => Ok(LinkProbe::Dns {
+                    host: target.to_string(),
+                    server: None,
+                }),
+            },
+            _ => Err(Error::Config(format!("unknown link probe {}", s))),
+        }
+    }
+}
+
+impl LinkProbe {
+    /// Protocol as used in the `protocol` label.
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            LinkProbe::Icmp(_) => "icmp",
+            LinkProbe::Tcp(_) => "tcp",
+            LinkProbe::Http(_) => "http",
+            LinkProbe::Dns { .. } => "dns",
+        }
+    }
+
+    /// Target as used in the `target` label.
+    pub fn target(&self) -> String {
+        match self {
+            LinkProbe::Icmp(target) | LinkProbe::Tcp(target) => target.clone(),
+            LinkProbe::Http(uri) => uri.to_string(),
+            LinkProbe::Dns { host, server: None } => host.clone(),
+            LinkProbe::Dns {
+                host,
+                server: Some(server),
+            } => format!("{}@{}", host, server),
+        }
+    }
+}
+
+/// Probes run by the exporter through the link of the dish, complementing the values reported by the dish itself.
+#[derive(Debug)]
+pub struct LinkProbes {
+    probes: Vec<LinkProbe>,
+    timeout: Duration,
+
+    duration_seconds: HistogramVec,
+    success: GaugeVec,
+}
+
+impl LinkProbes {
+    pub fn new(probes: Vec<LinkProbe>, timeout: Duration) -> Result<Self, Error> {
+        let link_probes = LinkProbes {
+            probes,
+            timeout,
+
+            duration_seconds: HistogramVec::new(
+                HistogramOpts::new(
+                    "link_probe_duration_seconds",
+                    "Link probe: Duration of successful probes of the target in seconds.",
+                ),
+                &["protocol", "target"],
+            )?,
+            success: GaugeVec::new(
+                Opts::new(
+                    "link_probe_success",
+                    "Link probe: Whether the last probe of the target succeeded.",
+                ),
+                &["protocol", "target"],
+            )?,
+        };
+
+        Ok(link_probes)
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<(), Error> {
+        registry.register(Box::new(self.duration_seconds.clone()))?;
+        registry.register(Box::new(self.success.clone()))?;
+
+        Ok(())
+    }
+
+    /// Spawns a task per probe, running it every `interval` until shutdown.
+    pub fn spawn(self, interval: Duration, shutdown: Shutdown) {
+        let client = Client::builder().build(
+            HttpsConnectorBuilder::new()
+                .with_webpki_roots()
+                .https_or_http()
+                .enable_http1()
+                .build(),
+        );
+
+        for probe in self.probes {
+            let labels = [probe.protocol().to_string(), probe.target()];
+            let runner = Runner {
+                probe,
+                timeout: self.timeout,
+                client: client.clone(),
+                duration_seconds: self.duration_seconds.with_label_values(&[&labels[0], &labels[1]]),
+                success: self.success.with_label_values(&[&labels[0], &labels[1]]),
+            };
+
+            tokio::spawn(runner.run(interval, shutdown.clone()));
+        }
+    }
+}
+
+struct Runner {
+    probe: LinkProbe,
+    timeout: Duration,
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+
+    duration_seconds: Histogram,
+    success: Gauge,
+}
+
+impl Runner {
+    async fn run(self, interval: Duration, mut shutdown: Shutdown) {
+        let mut interval = time::interval(interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {},
+                _ = shutdown.triggered() => return,
+            }
+
+            let start = Instant::now();
+            match self.probe_with_timeout().await {
+                Ok(()) => {
+                    self.duration_seconds.observe(start.elapsed().as_secs_f64());
+                    self.success.set(1_f64);
+                },
+                Err(e) => {
+                    debug!(
+                        "{} probe of {} failed: {}",
+                        self.probe.protocol(),
+                        self.probe.target(),
+                        e
+                    );
+                    self.success.set(0_f64);
+                },
+            }
+        }
+    }
+
+    async fn probe_with_timeout(&self) -> Result<(), ProbeError> {
+        match time::timeout(self.timeout, self.probe()).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("timed out after {:?}", self.timeout).into()),
+        }
+    }
+
+    async fn probe(&self) -> Result<(), ProbeError> {
+        match &self.probe {
+            LinkProbe::Icmp(host) => {
+                let address = resolve(host).await?;
+                let config = match address {
+                    IpAddr::V4(_) => surge_ping::Config::default(),
+                    IpAddr::V6(_) => surge_ping::Config::builder().kind(ICMP::V6).build(),
+                };
+
+                let client = surge_ping::Client::new(&config)?;
+                let mut pinger = client.pinger(address, PingIdentifier(std::process::id() as u16)).await;
+                pinger.timeout(self.timeout);
+                pinger.ping(PingSequence(0), &[0; 56]).await?;
+            },
+            LinkProbe::Tcp(address) => {
+                TcpStream::connect(address).await?;
+            },
+            LinkProbe::Http(uri) => {
+                let response = self.client.get(uri.clone()).await?;
+                let status = response.status();
+                hyper::body::to_bytes(response.into_body()).await?;
+
+                if status.is_client_error() || status.is_server_error() {
+                    return Err(format!("responded with {}", status).into());
+                }
+            },
+            LinkProbe::Dns { host, server: None } => {
+                resolve(host).await?;
+            },
+            LinkProbe::Dns {
+                host,
+                server: Some(server),
+            } => query(host, *server).await?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Resolves `host` with the resolver of the system, returning the first address.
+async fn resolve(host: &str) -> Result<IpAddr, io::Error> {
+    net::lookup_host((host, 0))
+        .await?
+        .next()
+        .map(|address| address.ip())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", host)))
+}
+
+/// Queries `server` over UDP for the A record of `host`, failing on error responses and responses without answers.
+async fn query(host: &str, server: SocketAddr) -> Result<(), ProbeError> {
+    // header: ID, recursion desired, one question
+    let id = ((unix_time_f64().fract() * u16::MAX as f64) as u16).to_be_bytes();
+    let mut message = vec![id[0], id[1], 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("invalid host name {}", host).into());
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    // root label, type A, class IN
+    message.extend_from_slice(&[0, 0, 1, 0, 1]);
+
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0_u16; 8], 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(server).await?;
+    socket.send(&message).await?;
+
+    let mut response = [0; 512];
+    loop {
+        let len = socket.recv(&mut response).await?;
+        // responses to earlier, timed out queries are ignored
+        if len < 12 || response[..2] != id || response[2] & 0x80 == 0 {
+            continue;
+        }
+
+        return match (response[3] & 0x0f, u16::from_be_bytes([response[6], response[7]])) {
+            (0, 0) => Err("no answer".into()),
+            (0, _) => Ok(()),
+            (rcode, _) => Err(format!("responded with rcode {}", rcode).into()),
+        };
+    }
+}
+
+/// Parses the DNS server of a probe, as IP address with an optional port.
+fn parse_server(s: &str) -> Result<SocketAddr, Error> {
+    s.parse::<SocketAddr>()
+        .or_else(|_| s.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+        .map_err(|_| Error::Config(format!("parsing link probe DNS server {}", s)))
+}
+
+fn parse_uri(s: &str) -> Result<Uri, Error> {
+    match s.parse::<Uri>() {
+        Ok(uri) if uri.scheme().is_some() && uri.host().is_some() => Ok(uri),
+        _ => Err(Error::Config(format!("parsing link probe URL {}", s))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use warp::{http::StatusCode, Filter};
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    async fn probe(probe: &str) -> Result<(), ProbeError> {
+        let runner = Runner {
+            probe: probe.parse().unwrap(),
+            timeout: TIMEOUT,
+            client: Client::builder().build(
+                HttpsConnectorBuilder::new()
+                    .with_webpki_roots()
+                    .https_or_http()
+                    .enable_http1()
+                    .build(),
+            ),
+            duration_seconds: Histogram::with_opts(HistogramOpts::new("duration_seconds", "Duration.")).unwrap(),
+            success: Gauge::new("success", "Success.").unwrap(),
+        };
+
+        runner.probe_with_timeout().await
+    }
+
+    /// Local address nothing listens on.
+    async fn closed_address() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        listener.local_addr().unwrap()
+    }
+
+    /// Answers each DNS query with `rcode` and `answers` answer records, or not at all if `None`.
+    async fn dns_server(response: Option<(u8, u16)>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                let (rcode, answers) = match response {
+                    Some(response) => response,
+                    None => continue,
+                };
+
+                let mut message = buf[..len].to_vec();
+                message[2] |= 0x80;
+                message[3] = rcode;
+                message[6..8].copy_from_slice(&answers.to_be_bytes());
+                for _ in 0..answers {
+                    // name pointing to the question, type A, class IN, TTL, address
+                    message.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 127, 0, 0, 1]);
+                }
+                socket.send_to(&message, peer).await.unwrap();
+            }
+        });
+
+        address
+    }
+
+    #[test]
+    fn parses_probes() {
+        assert_eq!(
+            "icmp:1.1.1.1".parse::<LinkProbe>().unwrap(),
+            LinkProbe::Icmp("1.1.1.1".to_string())
+        );
+        assert_eq!(
+            "tcp:vpn.example.com:443".parse::<LinkProbe>().unwrap(),
+            LinkProbe::Tcp("vpn.example.com:443".to_string())
+        );
+        assert_eq!(
+            "https://example.com/health".parse::<LinkProbe>().unwrap(),
+            LinkProbe::Http("https://example.com/health".parse().unwrap())
+        );
+        assert_eq!(
+            "http:http://example.com".parse::<LinkProbe>().unwrap(),
+            LinkProbe::Http("http://example.com".parse().unwrap())
+        );
+        assert_eq!("dns:example.com".parse::<LinkProbe>().unwrap(), LinkProbe::Dns {
+            host: "example.com".to_string(),
+            server: None,
+        });
+        assert_eq!(
+            "dns:example.com@1.1.1.1".parse::<LinkProbe>().unwrap(),
+            LinkProbe::Dns {
+                host: "example.com".to_string(),
+                server: Some(([1, 1, 1, 1], 53).into()),
+            }
+        );
+        assert_eq!(
+            "dns:example.com@[::1]:5353".parse::<LinkProbe>().unwrap().target(),
+            "example.com@[::1]:5353"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_probes() {
+        for probe in [
+            "example.com",
+            "ftp:example.com",
+            "http:example.com",
+            "http:not a url",
+            "dns:example.com@resolver",
+        ] {
+            assert!(probe.parse::<LinkProbe>().is_err(), "{}", probe);
+        }
+    }
+
+    #[tokio::test]
+    async fn tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        assert!(probe(&format!("tcp:{}", listener.local_addr().unwrap())).await.is_ok());
+        assert!(probe(&format!("tcp:{}", closed_address().await)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn http() {
+        let routes = warp::path("ok")
+            .map(|| "OK")
+            .or(warp::path("error").map(|| warp::reply::with_status("error", StatusCode::INTERNAL_SERVER_ERROR)));
+        let (address, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        assert!(probe(&format!("http://{}/ok", address)).await.is_ok());
+        assert!(probe(&format!("http://{}/error", address)).await.is_err());
+        assert!(probe(&format!("http://{}/", closed_address().await)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dns() {
+        assert!(probe(&format!("dns:example.com@{}", dns_server(Some((0, 1))).await))
+            .await
+            .is_ok());
+        // NXDOMAIN
+        assert!(probe(&format!("dns:example.com@{}", dns_server(Some((3, 0))).await))
+            .await
+            .is_err());
+        assert!(probe(&format!("dns:example.com@{}", dns_server(Some((0, 0))).await))
+            .await
+            .is_err());
+        assert!(probe("dns:localhost").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn times_out() {
+        // accepts connections, but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let start = Instant::now();
+        assert!(probe(&format!("http://{}/", address)).await.is_err());
+        assert!(probe(&format!("dns:example.com@{}", dns_server(None).await))
+            .await
+            .is_err());
+        assert!(start.elapsed() >= TIMEOUT * 2);
+    }
+}
@@ -0,0 +1,189 @@
+use futures_util::{future::Either, Stream, StreamExt};
+use listenfd::ListenFd;
+use std::{
+    fmt,
+    fs,
+    io,
+    net::SocketAddr,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener},
+    time,
+};
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
+use tracing::error;
+
+use crate::{exporter_error::Error, exporter_metrics::ExporterMetrics};
+
+/// Pause after accept errors besides aborted connections, which are mostly caused by running out of file descriptors
+/// and persist until connections are closed.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Address to listen on. Either a TCP socket address or the path of a Unix domain socket, prefixed with `unix:`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for BindAddress {
+    type Err = std::net::AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some(path) => Ok(BindAddress::Unix(PathBuf::from(path))),
+            None => Ok(BindAddress::Tcp(s.parse()?)),
+        }
+    }
+}
+
+impl fmt::Display for BindAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindAddress::Tcp(address) => write!(f, "{}", address),
+            BindAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Accepted connection of either listener type.
+pub trait Connection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Connection for T {}
+
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub async fn bind(address: &BindAddress) -> Result<Self, Error> {
+        match address {
+            BindAddress::Tcp(address) => Ok(Listener::Tcp(TcpListener::bind(address).await?)),
+            BindAddress::Unix(path) => {
+                // a socket left behind by a previous run would make binding fail
+                if let Ok(metadata) = fs::symlink_metadata(path) {
+                    if metadata.file_type().is_socket() {
+                        fs::remove_file(path)?;
+                    }
+                }
+
+                Ok(Listener::Unix(UnixListener::bind(path)?))
+            },
+        }
+    }
+
+    /// Takes the first socket passed via systemd socket activation, if `LISTEN_FDS` is set.
+    pub fn from_systemd() -> Result<Option<Self>, Error> {
+        let mut listen_fd = ListenFd::from_env();
+        if listen_fd.len() == 0 {
+            return Ok(None);
+        }
+
+        if let Ok(Some(listener)) = listen_fd.take_tcp_listener(0) {
+            listener.set_nonblocking(true)?;
+            return Ok(Some(Listener::Tcp(TcpListener::from_std(listener)?)));
+        }
+
+        match listen_fd.take_unix_listener(0)? {
+            Some(listener) => {
+                listener.set_nonblocking(true)?;
+                Ok(Some(Listener::Unix(UnixListener::from_std(listener)?)))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Address the listener is bound to, which differs from the configured one for sockets passed by systemd.
+    pub fn local_address(&self) -> Result<BindAddress, Error> {
+        match self {
+            Listener::Tcp(listener) => Ok(BindAddress::Tcp(listener.local_addr()?)),
+            Listener::Unix(listener) => Ok(BindAddress::Unix(
+                listener
+                    .local_addr()?
+                    .as_pathname()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default(),
+            )),
+        }
+    }
+
+    /// Accepted connections. Accept errors are logged, counted and skipped, as hyper would stop serving on the first
+    /// one.
+    pub fn incoming(
+        self,
+        exporter_metrics: ExporterMetrics,
+    ) -> impl Stream<Item = Box<dyn Connection>> + Send + 'static {
+        let connections = match self {
+            Listener::Tcp(listener) =>
+                Either::Left(TcpListenerStream::new(listener).map(|c| c.map(|c| Box::new(c) as Box<dyn Connection>))),
+            Listener::Unix(listener) =>
+                Either::Right(UnixListenerStream::new(listener).map(|c| c.map(|c| Box::new(c) as Box<dyn Connection>))),
+        };
+
+        skip_errors(connections, exporter_metrics)
+    }
+}
+
+fn skip_errors<C>(
+    connections: impl Stream<Item = io::Result<C>> + Send + 'static,
+    exporter_metrics: ExporterMetrics,
+) -> impl Stream<Item = C> + Send + 'static
+where
+    C: Send + 'static,
+{
+    connections.filter_map(move |connection| {
+        let exporter_metrics = exporter_metrics.clone();
+
+        async move {
+            let e = match connection {
+                Ok(connection) => return Some(connection),
+                Err(e) => e,
+            };
+
+            error!("accepting connection: {}", e);
+            let backoff = !matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset | io::ErrorKind::Interrupted
+            );
+            exporter_metrics.observe_error(&e.into());
+            if backoff {
+                time::sleep(ACCEPT_ERROR_BACKOFF).await;
+            }
+
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+
+    #[tokio::test]
+    async fn skips_accept_errors() {
+        let exporter_metrics = ExporterMetrics::new().unwrap();
+        let connections = stream::iter(vec![
+            Ok(1),
+            Err(io::Error::from(io::ErrorKind::ConnectionAborted)),
+            Ok(2),
+            // EMFILE
+            Err(io::Error::from_raw_os_error(24)),
+            Ok(3),
+        ]);
+
+        let accepted = skip_errors(connections, exporter_metrics.clone())
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(accepted, [1, 2, 3]);
+        assert_eq!(exporter_metrics.errors_total.with_label_values(&["io"]).get(), 2_f64);
+    }
+}
@@ -0,0 +1,42 @@
+use std::{cmp, str::FromStr};
+use tracing::Level;
+use tracing_subscriber::EnvFilter;
+
+use crate::exporter_error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(Error::Config(format!("unknown log format {}", s))),
+        }
+    }
+}
+
+/// Installs the global subscriber, logging the exporter at `level` and its dependencies at `level` but at most `INFO`.
+/// `RUST_LOG` takes precedence if set, for finer-grained filtering.
+pub fn init(level: Level, format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::new(format!(
+            "{},{}={}",
+            cmp::min(level, Level::INFO),
+            env!("CARGO_CRATE_NAME"),
+            level
+        ))
+    });
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
@@ -1,112 +1,500 @@
+use futures_util::StreamExt;
 use prometheus::{Encoder, Registry, TextEncoder};
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
-use tokio::sync::Mutex;
-use tracing::info;
+use starlink_exporter::{dish::Dish, metrics::Metrics};
+use std::{collections::HashMap, io, net::SocketAddr, sync::Arc, time::Duration};
+use tracing::{debug, error, info};
 use warp::{
     http,
-    hyper::{self, header::CONTENT_TYPE},
+    hyper::{
+        self,
+        header::{CONTENT_ENCODING, CONTENT_TYPE, VARY},
+    },
     Filter,
 };
 
-use crate::{error::Error, metrics::Metrics};
-use starlink::proto::space_x::api::device::{
-    device_client::DeviceClient,
-    request,
-    response,
-    GetDeviceInfoRequest,
-    Request,
+use crate::{
+    admin::{Action, Admin},
+    compression::Encoding,
+    config::Config,
+    export::Exporter,
+    exporter_error::Error,
+    exporter_metrics::ExporterMetrics,
+    filter::MetricFilter,
+    link_probes::LinkProbes,
+    listener::{BindAddress, Listener},
+    obstruction_trend::ObstructionTrend,
+    poller::Poller,
+    probes::{PingProbe, SpeedTestProbe},
+    sla::Sla,
+    storage::Storage,
+    summaries::Summaries,
+    watchdog::Watchdog,
+    web_config::WebConfig,
 };
 
-mod error;
-mod metrics;
+mod admin;
+mod auth;
+mod compression;
+mod config;
+mod events;
+mod export;
+mod exporter_error;
+mod exporter_metrics;
+mod filter;
+mod health;
+mod history;
+mod landing;
+mod link_probes;
+mod listener;
+mod logging;
+#[cfg(test)]
+mod mock_dish;
+mod obstruction_trend;
+mod poller;
+mod probes;
+mod schedule;
+mod shutdown;
+mod sla;
+mod storage;
+mod stream;
+mod summaries;
+mod systemd;
+mod tls;
+mod watchdog;
+mod web_config;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    tracing_subscriber::fmt::init();
+    let Config {
+        bind_address,
+        starlink_address,
+        poll_interval,
+        ready_max_age,
+        web_config_file,
+        metrics_include,
+        metrics_exclude,
+        disabled_metric_groups,
+        const_labels,
+        id_label,
+        compression_min_bytes,
+        request_timeout,
+        scrape_timeout_offset,
+        shutdown_grace_period,
+        log_level,
+        log_format,
+        log_reboots,
+        admin_token,
+        stow_schedule,
+        unstow_schedule,
+        watchdog_rules,
+        watchdog_max_reboots_per_day,
+        watchdog_dry_run,
+        probe_ping_targets,
+        probe_ping_interval,
+        probe_speed_test_schedule,
+        link_probes,
+        link_probe_interval,
+        link_probe_timeout,
+        storage_path,
+        storage_retention,
+        storage_history,
+        export_dir,
+        export_retention,
+        export_parquet,
+        sla_metrics,
+        sla_history,
+        summary_windows,
+        summary_history,
+        obstruction_trend_path,
+        obstruction_trend_threshold,
+    } = Config::from_env()?;
 
-    let bind_address = dotenv::var("BIND_ADDRESS")
-        .unwrap_or("0.0.0.0:9184".to_string())
-        .parse::<SocketAddr>()
-        .expect("parsing BIND_ADDRESS");
-    let starlink_address = dotenv::var("STARLINK_ADDRESS").unwrap_or("http://dishy.starlink.com:9200".to_string());
+    logging::init(log_level, log_format);
 
-    info!("connecting ro Starlink device on {}", &starlink_address);
+    let web_config = match web_config_file {
+        Some(web_config_file) => {
+            info!("reading web config from {}", web_config_file.display());
+
+            WebConfig::from_file(&web_config_file)?
+        },
+        None => WebConfig::default(),
+    };
 
-    let mut client = DeviceClient::connect(starlink_address.clone()).await?;
+    let exporter_registry = Registry::new_custom(None, Some(const_labels.clone()).filter(|l| !l.is_empty()))?;
+    let exporter_metrics = ExporterMetrics::new()?;
+    exporter_metrics.register(&exporter_registry)?;
 
-    let req = tonic::Request::new(Request {
-        request: Some(request::Request::GetDeviceInfo(GetDeviceInfoRequest {})),
-        ..Default::default()
-    });
-    let res = client.handle(req).await?.into_inner();
+    info!("connecting ro Starlink device on {}", &starlink_address);
+
+    let dish = Dish::connect(starlink_address)
+        .await?
+        .with_request_duration(exporter_metrics.grpc_request_duration_seconds.clone())
+        .with_timeout(request_timeout);
 
     let mut labels = HashMap::new();
+    // logged along with every poll, unless hidden
+    let mut dish_id = None;
 
-    if let Some(response::Response::GetDeviceInfo(r)) = res.response {
-        if let Some(device_info) = r.device_info {
-            if let Some(id) = device_info.id {
-                info!("setting registry label id = {}", &id);
-                labels.insert("id".to_string(), id);
-            }
-            if let Some(hardware_version) = device_info.hardware_version {
-                info!("setting registry label hardware_version = {}", &hardware_version);
-                labels.insert("hardware_version".to_string(), hardware_version);
-            }
-            // `software_version` & `country_code` are subject to change at runtime
-            // if let Some(software_version) = device_info.software_version {
-            //     info!("setting registry label software_version = {}", &software_version);
-            //     labels.insert("software_version".to_string(), software_version);
-            // }
-            // if let Some(country_code) = device_info.country_code {
-            //     info!("setting registry label country_code = {}", &country_code);
-            //     labels.insert("country_code".to_string(), country_code);
-            // }
+    if let Some(device_info) = dish.get_device_info().await? {
+        match (device_info.id, &id_label) {
+            (Some(id), Some(id_label)) => {
+                info!("setting registry label {} = {}", id_label, &id);
+                labels.insert(id_label.clone(), id.clone());
+                dish_id = Some(id);
+            },
+            (Some(_), None) => info!("hiding dish id"),
+            (None, _) => {},
+        }
+        if let Some(hardware_version) = device_info.hardware_version {
+            info!("setting registry label hardware_version = {}", &hardware_version);
+            labels.insert("hardware_version".to_string(), hardware_version);
         }
+        // `software_version` & `country_code` are subject to change at runtime
+        // if let Some(software_version) = device_info.software_version {
+        //     info!("setting registry label software_version = {}", &software_version);
+        //     labels.insert("software_version".to_string(), software_version);
+        // }
+        // if let Some(country_code) = device_info.country_code {
+        //     info!("setting registry label country_code = {}", &country_code);
+        //     labels.insert("country_code".to_string(), country_code);
+        // }
+    }
+
+    for (name, value) in const_labels {
+        info!("setting registry label {} = {}", &name, &value);
+        labels.insert(name, value);
     }
 
     let registry = Registry::new_custom(Some("starlink".to_string()), Some(labels))?;
 
     let metrics = Metrics::new()?;
-    metrics.register(&registry)?;
-    let metrics = Arc::new(Mutex::new(metrics));
-
-    let route = warp::get()
-        .and(warp::path("metrics"))
-        .and(warp::addr::remote())
-        .and_then(move |addr: Option<SocketAddr>| {
-            if let Some(addr) = addr {
-                info!("incoming request from {}", addr);
-            }
+    metrics.register(&registry, &disabled_metric_groups)?;
+
+    let metric_filter = MetricFilter::new(&metrics_include, &metrics_exclude)?;
+    let (trigger, shutdown) = shutdown::channel();
+
+    // probes and admin actions share the channel of the poller's client
+    if !probe_ping_targets.is_empty() {
+        info!(
+            "pinging {} from Starlink device every {:?}",
+            probe_ping_targets.join(", "),
+            &probe_ping_interval
+        );
+
+        let probe = PingProbe::new(dish.clone(), probe_ping_targets, exporter_metrics.clone())?;
+        probe.register(&registry)?;
+        tokio::spawn(probe.run(probe_ping_interval, shutdown.clone()));
+    }
+
+    if let Some(schedule) = probe_speed_test_schedule {
+        info!("scheduling speed tests on Starlink device");
+
+        let probe = SpeedTestProbe::new(dish.clone(), exporter_metrics.clone())?;
+        probe.register(&registry)?;
+        tokio::spawn(probe.run(schedule, shutdown.clone()));
+    }
+
+    if !link_probes.is_empty() {
+        info!(
+            "running {} link probes every {:?}",
+            link_probes.len(),
+            &link_probe_interval
+        );
+
+        let link_probes = LinkProbes::new(link_probes, link_probe_timeout)?;
+        link_probes.register(&registry)?;
+        link_probes.spawn(link_probe_interval, shutdown.clone());
+    }
+
+    let admin = Admin::new(dish.clone(), exporter_metrics.clone());
+    let sla = match sla_metrics {
+        true => {
+            let sla = Sla::new(sla_history)?;
+            sla.register(&registry)?;
+
+            Some(sla)
+        },
+        false => None,
+    };
+    let summaries = match summary_windows.is_empty() {
+        true => None,
+        false => {
+            let summaries = Summaries::new(summary_windows, summary_history)?;
+            summaries.register(&registry)?;
+
+            Some(Arc::new(summaries))
+        },
+    };
+    let obstruction_trend = match obstruction_trend_path {
+        Some(path) => {
+            info!("tracking obstruction trend in {}", path.display());
+
+            let obstruction_trend =
+                ObstructionTrend::open(path, obstruction_trend_threshold, exporter_metrics.clone())?;
+            obstruction_trend.register(&registry)?;
+
+            Some(obstruction_trend)
+        },
+        None => None,
+    };
+    // the history is read once by the poller for all its consumers
+    let history = (storage_path.is_some() && storage_history)
+        || (sla.is_some() && sla_history)
+        || (summaries.is_some() && summary_history);
+    let poller =
+        Arc::new(Poller::new(dish, dish_id, log_reboots, metrics, exporter_metrics.clone()).with_history(history));
+
+    for (action, schedule) in [(Action::Stow, stow_schedule), (Action::Unstow, unstow_schedule)] {
+        if let Some(schedule) = schedule {
+            info!("scheduling {} of Starlink device", action.as_str());
+
+            tokio::spawn(schedule::run(admin.clone(), action, schedule, shutdown.clone()));
+        }
+    }
+
+    if !watchdog_rules.is_empty() {
+        info!(
+            "watching Starlink device with {} rules{}",
+            watchdog_rules.len(),
+            if watchdog_dry_run { " in dry-run mode" } else { "" }
+        );
+
+        let watchdog = Watchdog::new(
+            watchdog_rules,
+            watchdog_max_reboots_per_day,
+            watchdog_dry_run,
+            admin.clone(),
+            exporter_metrics.clone(),
+        );
+        tokio::spawn(watchdog.run(poller.events("watchdog", shutdown.clone())));
+    }
+
+    let storage = match storage_path {
+        Some(storage_path) => {
+            info!("storing metrics in {}", storage_path.display());
+
+            let storage = Arc::new(Storage::open(&storage_path, storage_retention)?);
+            tokio::spawn(storage.clone().run(
+                poller.events("storage", shutdown.clone()),
+                storage_history,
+                exporter_metrics.clone(),
+            ));
+
+            Some(storage)
+        },
+        None => None,
+    };
 
-            let starlink_address = starlink_address.clone();
-            let metrics = metrics.clone();
-            let registry = registry.clone();
+    if let Some(sla) = sla {
+        info!(
+            "computing SLA metrics from {}",
+            if sla_history { "history" } else { "polled state" }
+        );
 
-            async move {
-                let mut metrics = metrics.lock().await;
-                metrics.update(starlink_address).await?;
+        tokio::spawn(sla.run(poller.events("SLA computation", shutdown.clone())));
+    }
 
-                let encoder = TextEncoder::new();
+    if let Some(summaries) = &summaries {
+        info!(
+            "computing summaries from {}",
+            if summary_history { "history" } else { "polled values" }
+        );
 
-                let metric_families = registry.gather();
-                let mut buffer = vec![];
-                encoder
-                    .encode(&metric_families, &mut buffer)
-                    .map_err(|e| Error::from(e))?;
+        tokio::spawn(summaries.clone().run(poller.events("summaries", shutdown.clone())));
+    }
 
-                let response = http::Response::builder()
-                    .status(200)
-                    .header(CONTENT_TYPE, encoder.format_type())
-                    .body(hyper::Body::from(buffer))
-                    .map_err(|e| Error::from(e))?;
+    if let Some(obstruction_trend) = obstruction_trend {
+        tokio::spawn(obstruction_trend.run(
+            poller.clone(),
+            poller.statuses("obstruction trend", shutdown.clone()),
+            shutdown.clone(),
+        ));
+    }
 
-                Ok(response) as Result<hyper::Response<hyper::Body>, warp::Rejection>
+    if let Some(export_dir) = export_dir {
+        info!(
+            "exporting dish status to {}{}",
+            export_dir.display(),
+            if export_parquet {
+                " as CSV and Parquet"
+            } else {
+                " as CSV"
             }
-        });
+        );
+
+        let exporter = Exporter::new(export_dir, export_retention, export_parquet, exporter_metrics.clone())?;
+        tokio::spawn(exporter.run(poller.statuses("export", shutdown.clone())));
+    }
+
+    // started once all consumers subscribed, so none misses the first poll, which reads the whole history
+    if let Some(poll_interval) = poll_interval {
+        info!("polling Starlink device every {:?}", &poll_interval);
+
+        tokio::spawn(poller.clone().run(poll_interval, shutdown.clone()));
+    }
+
+    let health_routes = health::routes(poller.clone(), ready_max_age);
+    let admin_routes = admin::routes(admin, admin_token);
+    let landing_route = landing::route(poller.clone());
+    let stream_route = stream::route(poller.clone(), shutdown.clone());
+    let query_route = storage::route(storage.clone());
+    let export_route = export::route(storage);
+    let summaries_route = summaries::route(summaries);
+
+    let metrics_route = {
+        let exporter_metrics = exporter_metrics.clone();
+
+        warp::get()
+            .and(warp::path("metrics"))
+            .and(warp::addr::remote())
+            .and(warp::header::optional::<String>("accept-encoding"))
+            .and(warp::header::optional::<f64>("x-prometheus-scrape-timeout-seconds"))
+            .and_then(
+                move |addr: Option<SocketAddr>, accept_encoding: Option<String>, scrape_timeout: Option<f64>| {
+                    if let Some(addr) = addr {
+                        debug!("incoming request from {}", addr);
+                    }
+
+                    let poller = poller.clone();
+                    let registries = [registry.clone(), exporter_registry.clone()];
+                    let metric_filter = metric_filter.clone();
+                    let exporter_metrics = exporter_metrics.clone();
+
+                    async move {
+                        // with background polling enabled, scrapes serve the values of the latest poll
+                        if poll_interval.is_none() {
+                            // leave part of the scrape timeout to respond with the cached values if the dish is slow
+                            let timeout = scrape_timeout
+                                .and_then(|s| Duration::try_from_secs_f64(s).ok())
+                                .map(|s| s.saturating_sub(scrape_timeout_offset).min(request_timeout));
+
+                            if let Err(e) = poller.poll(timeout).await {
+                                error!("polling Starlink device: {}", e);
+                            }
+                        }
+
+                        let encoding = accept_encoding.as_deref().and_then(Encoding::negotiate);
+                        let response = encode(&registries, &metric_filter, encoding, compression_min_bytes);
+                        if let Err(e) = &response {
+                            exporter_metrics.observe_error(e);
+                        }
+
+                        Ok(response?) as Result<hyper::Response<hyper::Body>, warp::Rejection>
+                    }
+                },
+            )
+    };
+
+    // probes are exempt from authentication, admin routes authenticate on their own
+    let routes = health_routes
+        .or(admin_routes)
+        .or(auth::filter(&web_config).and(
+            landing_route
+                .or(metrics_route)
+                .or(stream_route)
+                .or(query_route)
+                .or(summaries_route)
+                .or(export_route),
+        ))
+        .recover(auth::recover)
+        .with({
+            let exporter_metrics = exporter_metrics.clone();
+
+            warp::log::custom(move |info| {
+                exporter_metrics
+                    .http_requests_total
+                    .with_label_values(&[path_label(info.path()), info.status().as_str()])
+                    .inc();
+            })
+        })
+        .with(warp::reply::with::headers(web_config.http_server_config.header_map()?));
 
-    info!("binding Prometheus exporter on http://{}", &bind_address);
+    let server = warp::serve(routes);
+    let signal = shutdown.wait();
+    let listener = match Listener::from_systemd()? {
+        Some(listener) => {
+            info!("using socket passed by systemd");
 
-    warp::serve(route).run(bind_address).await;
+            listener
+        },
+        None => match (&bind_address, &web_config.tls_server_config) {
+            // letting warp bind plain TCP sockets itself keeps the remote address of requests available
+            (BindAddress::Tcp(bind_address), None) => {
+                let (bind_address, server) = server.bind_with_graceful_shutdown(*bind_address, signal);
+
+                info!("binding Prometheus exporter on http://{}", &bind_address);
+                systemd::notify_ready();
+
+                return shutdown::run_until_signal(server, trigger, shutdown_grace_period).await;
+            },
+            (bind_address, _) => Listener::bind(bind_address).await?,
+        },
+    };
+
+    match &web_config.tls_server_config {
+        Some(tls_server_config) => {
+            let server_config = tls::server_config(tls_server_config, web_config.http_server_config.http2)?;
+
+            info!("binding Prometheus exporter on https://{}", listener.local_address()?);
+            systemd::notify_ready();
+
+            let incoming = tls::incoming(listener.incoming(exporter_metrics), server_config);
+            let server = server.serve_incoming_with_graceful_shutdown(incoming, signal);
+            shutdown::run_until_signal(server, trigger, shutdown_grace_period).await?;
+        },
+        None => {
+            info!("binding Prometheus exporter on http://{}", listener.local_address()?);
+            systemd::notify_ready();
+
+            let incoming = listener.incoming(exporter_metrics).map(Ok::<_, io::Error>);
+            let server = server.serve_incoming_with_graceful_shutdown(incoming, signal);
+            shutdown::run_until_signal(server, trigger, shutdown_grace_period).await?;
+        },
+    }
 
     Ok(())
 }
+
+/// Encodes the metrics of all registries, compressing them with `encoding` if they're at least `min_bytes` large.
+fn encode(
+    registries: &[Registry],
+    metric_filter: &MetricFilter,
+    encoding: Option<Encoding>,
+    min_bytes: usize,
+) -> Result<hyper::Response<hyper::Body>, Error> {
+    let encoder = TextEncoder::new();
+
+    let metric_families = metric_filter.apply(registries.iter().flat_map(|r| r.gather()).collect());
+    let mut buffer = vec![];
+    encoder.encode(&metric_families, &mut buffer)?;
+
+    let mut response = http::Response::builder()
+        .status(200)
+        .header(CONTENT_TYPE, encoder.format_type())
+        .header(VARY, "Accept-Encoding");
+
+    if let Some(encoding) = encoding.filter(|_| buffer.len() >= min_bytes) {
+        buffer = encoding.compress(&buffer)?;
+        response = response.header(CONTENT_ENCODING, encoding.as_str());
+    }
+
+    Ok(response.body(hyper::Body::from(buffer))?)
+}
+
+/// Maps request paths to the served routes, keeping the cardinality of the `path` label bounded.
+fn path_label(path: &str) -> &'static str {
+    match path {
+        "/" => "/",
+        "/metrics" => "/metrics",
+        "/healthz" => "/healthz",
+        "/readyz" => "/readyz",
+        "/api/v1/stream" => "/api/v1/stream",
+        "/api/v1/query" => "/api/v1/query",
+        "/api/v1/summaries" => "/api/v1/summaries",
+        "/export.csv" => "/export.csv",
+        "/admin/reboot" => "/admin/reboot",
+        "/admin/stow" => "/admin/stow",
+        "/admin/unstow" => "/admin/unstow",
+        _ => "other",
+    }
+}
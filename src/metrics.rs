@@ -1,408 +1,481 @@
-use prometheus::{Counter, Gauge, GaugeVec, Opts, Registry};
-use std::collections::HashMap;
-use tracing::{debug, info};
-
-use crate::error::Error;
-use starlink::proto::space_x::api::device::{
-    device_client::DeviceClient,
-    request,
-    response,
-    GetStatusRequest,
-    Request,
-};
+use prometheus::{core::Collector, Counter, Gauge, GaugeVec, Opts, Registry};
+use std::{fmt, str::FromStr};
+use tracing::{debug, trace};
+
+use crate::{dish::Dish, error::Error, status::Status, util::unix_time};
+use starlink::proto::space_x::api::device::DishGetStatusResponse;
+
+/// Groups of metrics that can be disabled as a whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Group {
+    /// `device_info`
+    DeviceInfo,
+    /// All `alert_*` metrics.
+    Alerts,
+    /// All `obstruction_*` metrics except the wedges.
+    Obstruction,
+    /// `obstruction_wedge_fraction_obstructed` & `obstruction_wedge_abs_fraction_obstructed`
+    Wedges,
+}
 
-#[derive(Debug)]
-pub struct Metrics {
-    pub device_info: GaugeVec,
-
-    pub uptime_s: Counter,
-
-    pub state: Gauge,
-
-    pub alert_motors_stuck: Gauge,
-    pub alert_thermal_throttle: Gauge,
-    pub alert_thermal_shutdown: Gauge,
-    pub alert_mast_not_near_vertical: Gauge,
-    pub alert_unexpected_location: Gauge,
-    pub alert_slow_ethernet_speeds: Gauge,
-
-    pub snr: Gauge,
-    pub seconds_to_first_nonempty_slot: Gauge,
-    pub pop_ping_drop_rate: Gauge,
-    pub downlink_throughput_bps: Gauge,
-    pub uplink_throughput_bps: Gauge,
-    pub pop_ping_latency_ms: Gauge,
-
-    pub obstruction_currently_obstructed: Gauge,
-    pub obstruction_fraction_obstructed: Gauge,
-    pub obstruction_last_24h_obstructed_s: Counter,
-    pub obstruction_valid_s: Counter,
-    pub obstruction_wedge_fraction_obstructed: GaugeVec,
-    pub obstruction_wedge_abs_fraction_obstructed: GaugeVec,
+/// Mapping of the dish status to metrics, in the `dish` namespace. Registration, updates and the metrics table of the
+/// README are all generated from it, so exposing another field of the status only takes a new entry.
+#[rustfmt::skip]
+pub const DISH_METRICS: &[MetricDef] = &[
+    MetricDef::info("device_info", "Device information. Exposing `software_version` and `country_code` as additional labels.", &["software_version", "country_code"], |r| r.device_info.as_ref().map(|d| vec![d.software_version.clone().unwrap_or_default(), d.country_code.clone().unwrap_or_default()])).group(Group::DeviceInfo),
+    MetricDef::counter("uptime_s", "Dish uptime in seconds.", |r| Some(r.device_state.as_ref()?.uptime_s? as f64)).unit("seconds"),
+    MetricDef::gauge("boot_time_seconds", "Unix time the dish booted, derived from its uptime. Changes on reboots.", |r| Some(unix_time() as f64 - r.device_state.as_ref()?.uptime_s? as f64)).unit("seconds"),
+    MetricDef::gauge("state", "Dish state. 0: Unknown, 1: Connected, 2: Searching, 3: Booting.", |r| r.state.map(f64::from)),
+    MetricDef::gauge("stow_requested", "Whether stowing the dish was requested.", |r| r.stow_requested.map(bool_to_f64)),
+    MetricDef::gauge("alert_motors_stuck", "Alert: Motors stuck.", |r| r.alerts.as_ref()?.motors_stuck.map(bool_to_f64)).group(Group::Alerts),
+    MetricDef::gauge("alert_thermal_throttle", "Alert: Thermal throttle.", |r| r.alerts.as_ref()?.thermal_throttle.map(bool_to_f64)).group(Group::Alerts),
+    MetricDef::gauge("alert_thermal_shutdown", "Alert: Thermal shutdown.", |r| r.alerts.as_ref()?.thermal_shutdown.map(bool_to_f64)).group(Group::Alerts),
+    MetricDef::gauge("alert_mast_not_near_vertical", "Alert: Mast not near vertical.", |r| r.alerts.as_ref()?.mast_not_near_vertical.map(bool_to_f64)).group(Group::Alerts),
+    MetricDef::gauge("alert_unexpected_location", "Alert: Unexpected location.", |r| r.alerts.as_ref()?.unexpected_location.map(bool_to_f64)).group(Group::Alerts),
+    MetricDef::gauge("alert_slow_ethernet_speeds", "Alert: Slow ethernet speeds.", |r| r.alerts.as_ref()?.slow_ethernet_speeds.map(bool_to_f64)).group(Group::Alerts),
+    MetricDef::gauge("snr", "Signal-to-noise ratio.", |r| r.snr.map(f64::from)),
+    MetricDef::gauge("seconds_to_first_nonempty_slot", "Seconds to first non-empty slot.", |r| r.seconds_to_first_nonempty_slot.map(f64::from)).unit("seconds"),
+    MetricDef::gauge("pop_ping_drop_rate", "Pop ping drop rate.", |r| r.pop_ping_drop_rate.map(f64::from)).unit("ratio"),
+    MetricDef::gauge("downlink_throughput_bps", "Downlink throughput in Bps.", |r| r.downlink_throughput_bps.map(f64::from)).unit("bits per second"),
+    MetricDef::gauge("uplink_throughput_bps", "Uplink throughput in Bps.", |r| r.uplink_throughput_bps.map(f64::from)).unit("bits per second"),
+    MetricDef::gauge("pop_ping_latency_ms", "Pop ping latency in ms.", |r| r.pop_ping_latency_ms.map(f64::from)).unit("milliseconds"),
+    MetricDef::gauge("obstruction_currently_obstructed", "Obstruction: Currently obstructed.", |r| r.obstruction_stats.as_ref()?.currently_obstructed.map(bool_to_f64)).group(Group::Obstruction),
+    MetricDef::gauge("obstruction_fraction_obstructed", "Obstruction: Obstructed fraction. Sum of obstructed fractions.", |r| r.obstruction_stats.as_ref()?.fraction_obstructed.map(f64::from)).unit("ratio").group(Group::Obstruction),
+    MetricDef::gauge("obstruction_last_24h_obstructed_s", "Obstruction: Obstructed seconds in the last 24 hours.", |r| r.obstruction_stats.as_ref()?.last_24h_obstructed_s.map(f64::from)).unit("seconds").group(Group::Obstruction),
+    MetricDef::counter("obstruction_valid_s", "Obstruction: Valid seconds.", |r| r.obstruction_stats.as_ref()?.valid_s.map(f64::from)).unit("seconds").group(Group::Obstruction),
+    MetricDef::wedges("obstruction_wedge_fraction_obstructed", "Obstruction: Wedge fraction obstructed. Measure of obstruction in twelve 30 degree wedges around the dish.", |r| Some(&r.obstruction_stats.as_ref()?.wedge_fraction_obstructed)).unit("ratio").group(Group::Wedges),
+    MetricDef::wedges("obstruction_wedge_abs_fraction_obstructed", "Obstruction: Wedge fraction obstruction average. Measure of average obstruction in twelve 30 degree wedges around the dish.", |r| Some(&r.obstruction_stats.as_ref()?.wedge_abs_fraction_obstructed)).unit("ratio").group(Group::Wedges),
+];
+
+/// A metric of the dish status, see `DISH_METRICS`.
+pub struct MetricDef {
+    /// Name within the `dish` namespace.
+    pub name: &'static str,
+    pub help: &'static str,
+    pub unit: Option<&'static str>,
+    pub group: Option<Group>,
+    pub kind: Kind,
 }
 
-impl Metrics {
-    pub fn new() -> Result<Self, Error> {
-        let metrics = Metrics {
-            device_info: GaugeVec::new(Opts::new("device_info", "Device information. Exposing `software_version` and `country_code` as additional labels.").namespace("dish"), &[
-                // "id",
-                // "hardware_version",
-                "software_version",
-                "country_code",
-            ])?,
-
-            uptime_s: Counter::with_opts(Opts::new("uptime_s", "Dish uptime in seconds.").namespace("dish"))?,
-
-            state: Gauge::with_opts(
-                Opts::new(
-                    "state",
-                    "Dish state. 0: Unknown, 1: Connected, 2: Searching, 3: Booting.",
-                )
-                .namespace("dish"),
-            )?,
-
-            alert_motors_stuck: Gauge::with_opts(
-                Opts::new("motors_stuck", "Alert: Motors stuck.")
-                    .namespace("dish")
-                    .subsystem("alert"),
-            )?,
-            alert_thermal_throttle: Gauge::with_opts(
-                Opts::new("thermal_throttle", "Alert: Thermal throttle.")
-                    .namespace("dish")
-                    .subsystem("alert"),
-            )?,
-            alert_thermal_shutdown: Gauge::with_opts(
-                Opts::new("thermal_shutdown", "Alert: Thermal shutdown.")
-                    .namespace("dish")
-                    .subsystem("alert"),
-            )?,
-            alert_mast_not_near_vertical: Gauge::with_opts(
-                Opts::new("mast_not_near_vertical", "Alert: Mast not near vertical.")
-                    .namespace("dish")
-                    .subsystem("alert"),
-            )?,
-            alert_unexpected_location: Gauge::with_opts(
-                Opts::new("unexpected_location", "Alert: Unexpected location.")
-                    .namespace("dish")
-                    .subsystem("alert"),
-            )?,
-            alert_slow_ethernet_speeds: Gauge::with_opts(
-                Opts::new("slow_ethernet_speeds", "Alert: Slow ethernet speeds.")
-                    .namespace("dish")
-                    .subsystem("alert"),
-            )?,
-
-            snr: Gauge::with_opts(Opts::new("snr", "Signal-to-noise ratio.").namespace("dish"))?,
-            seconds_to_first_nonempty_slot: Gauge::with_opts(
-                Opts::new("seconds_to_first_nonempty_slot", "Seconds to first non-empty slot.").namespace("dish"),
-            )?,
-            pop_ping_drop_rate: Gauge::with_opts(
-                Opts::new("pop_ping_drop_rate", "Pop ping drop rate.").namespace("dish"),
-            )?,
-            downlink_throughput_bps: Gauge::with_opts(
-                Opts::new("downlink_throughput_bps", "Downlink throughput in Bps.").namespace("dish"),
-            )?,
-            uplink_throughput_bps: Gauge::with_opts(
-                Opts::new("uplink_throughput_bps", "Uplink throughput in Bps.").namespace("dish"),
-            )?,
-            pop_ping_latency_ms: Gauge::with_opts(
-                Opts::new("pop_ping_latency_ms", "Pop ping latency in ms.").namespace("dish"),
-            )?,
-
-            obstruction_currently_obstructed: Gauge::with_opts(
-                Opts::new("currently_obstructed", "Obstruction: Currently obstructed.")
-                    .namespace("dish")
-                    .subsystem("obstruction"),
-            )?,
-            obstruction_fraction_obstructed: Gauge::with_opts(
-                Opts::new("fraction_obstructed", "Obstruction: Obstructed fraction. Sum of obstructed fractions.")
-                    .namespace("dish")
-                    .subsystem("obstruction"),
-            )?,
-            obstruction_last_24h_obstructed_s: Counter::with_opts(
-                Opts::new("last_24h_obstructed_s", "Obstruction: Obstructed seconds in the last 24 hours.")
-                    .namespace("dish")
-                    .subsystem("obstruction"),
-            )?,
-            obstruction_valid_s: Counter::with_opts(
-                Opts::new("valid_s", "Obstruction: Valid seconds.")
-                    .namespace("dish")
-                    .subsystem("obstruction"),
-            )?,
-            obstruction_wedge_fraction_obstructed: GaugeVec::new(
-                Opts::new("wedge_fraction_obstructed", "Obstruction: Wedge fraction obstructed. Measure of obstruction in twelve 30 degree wedges around the dish.")
-                    .namespace("dish")
-                    .subsystem("obstruction"),
-                &["wedge"],
-            )?,
-            obstruction_wedge_abs_fraction_obstructed: GaugeVec::new(
-                Opts::new(
-                    "wedge_abs_fraction_obstructed",
-                    "Obstruction: Wedge fraction obstruction average. Measure of average obstruction in twelve 30 degree wedges around the dish.",
-                )
-                .namespace("dish")
-                .subsystem("obstruction"),
-                &["wedge"],
-            )?,
-        };
+/// Type of a metric, along with how to read its value from the dish status.
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Gauge(fn(&DishGetStatusResponse) -> Option<f64>),
+    /// Cumulative value kept by the dish, e.g. since it booted. Follows the reported value, resetting whenever it
+    /// decreases. Exposed along with a `_created` gauge holding the Unix time the count started at.
+    Counter(fn(&DishGetStatusResponse) -> Option<f64>),
+    /// One gauge per 30 degree wedge around the dish, labeled by `wedge`.
+    Wedges(fn(&DishGetStatusResponse) -> Option<&Vec<f32>>),
+    /// Constant `1`, exposing the values as the named labels.
+    Info(
+        &'static [&'static str],
+        fn(&DishGetStatusResponse) -> Option<Vec<String>>,
+    ),
+}
 
-        Ok(metrics)
+impl MetricDef {
+    pub const fn gauge(
+        name: &'static str,
+        help: &'static str,
+        value: fn(&DishGetStatusResponse) -> Option<f64>,
+    ) -> Self {
+        Self::new(name, help, Kind::Gauge(value))
     }
 
-    pub fn register(&self, registry: &Registry) -> Result<(), Error> {
-        registry.register(Box::new(self.device_info.clone()))?;
+    pub const fn counter(
+        name: &'static str,
+        help: &'static str,
+        value: fn(&DishGetStatusResponse) -> Option<f64>,
+    ) -> Self {
+        Self::new(name, help, Kind::Counter(value))
+    }
 
-        registry.register(Box::new(self.uptime_s.clone()))?;
+    pub const fn wedges(
+        name: &'static str,
+        help: &'static str,
+        values: fn(&DishGetStatusResponse) -> Option<&Vec<f32>>,
+    ) -> Self {
+        Self::new(name, help, Kind::Wedges(values))
+    }
 
-        registry.register(Box::new(self.state.clone()))?;
+    pub const fn info(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+        values: fn(&DishGetStatusResponse) -> Option<Vec<String>>,
+    ) -> Self {
+        Self::new(name, help, Kind::Info(labels, values))
+    }
 
-        registry.register(Box::new(self.alert_motors_stuck.clone()))?;
-        registry.register(Box::new(self.alert_thermal_throttle.clone()))?;
-        registry.register(Box::new(self.alert_thermal_shutdown.clone()))?;
-        registry.register(Box::new(self.alert_mast_not_near_vertical.clone()))?;
-        registry.register(Box::new(self.alert_unexpected_location.clone()))?;
-        registry.register(Box::new(self.alert_slow_ethernet_speeds.clone()))?;
+    const fn new(name: &'static str, help: &'static str, kind: Kind) -> Self {
+        MetricDef {
+            name,
+            help,
+            unit: None,
+            group: None,
+            kind,
+        }
+    }
 
-        registry.register(Box::new(self.snr.clone()))?;
-        registry.register(Box::new(self.seconds_to_first_nonempty_slot.clone()))?;
-        registry.register(Box::new(self.pop_ping_drop_rate.clone()))?;
-        registry.register(Box::new(self.downlink_throughput_bps.clone()))?;
-        registry.register(Box::new(self.uplink_throughput_bps.clone()))?;
-        registry.register(Box::new(self.pop_ping_latency_ms.clone()))?;
+    pub const fn unit(self, unit: &'static str) -> Self {
+        MetricDef {
+            unit: Some(unit),
+            ..self
+        }
+    }
 
-        registry.register(Box::new(self.obstruction_currently_obstructed.clone()))?;
-        registry.register(Box::new(self.obstruction_fraction_obstructed.clone()))?;
-        registry.register(Box::new(self.obstruction_last_24h_obstructed_s.clone()))?;
-        registry.register(Box::new(self.obstruction_valid_s.clone()))?;
-        registry.register(Box::new(self.obstruction_wedge_fraction_obstructed.clone()))?;
-        registry.register(Box::new(self.obstruction_wedge_abs_fraction_obstructed.clone()))?;
+    pub const fn group(self, group: Group) -> Self {
+        MetricDef {
+            group: Some(group),
+            ..self
+        }
+    }
 
-        Ok(())
+    /// Name of the Prometheus metric type.
+    pub fn type_name(&self) -> &'static str {
+        match self.kind {
+            Kind::Gauge(_) => "Gauge",
+            Kind::Counter(_) => "Counter",
+            Kind::Wedges(_) | Kind::Info(..) => "GaugeVec",
+        }
     }
 
-    pub async fn update(&mut self, starlink_address: String) -> Result<(), Error> {
-        let mut client = DeviceClient::connect(starlink_address).await?;
+    /// Labels and values of the metric in `response`, read like the metric itself. Counters yield the value reported by
+    /// the dish.
+    pub fn samples(&self, response: &DishGetStatusResponse) -> Vec<(Vec<(&'static str, String)>, f64)> {
+        match self.kind {
+            Kind::Gauge(value) | Kind::Counter(value) => value(response).map(|v| (vec![], v)).into_iter().collect(),
+            Kind::Wedges(values) => values(response)
+                .into_iter()
+                .flatten()
+                .enumerate()
+                .map(|(i, v)| (vec![("wedge", i.to_string())], *v as f64))
+                .collect(),
+            Kind::Info(labels, values) => values(response)
+                .map(|values| (labels.iter().copied().zip(values).collect(), 1_f64))
+                .into_iter()
+                .collect(),
+        }
+    }
 
-        info!("updating metrics from Starlink device");
+    fn build(&'static self) -> Result<DishMetric, Error> {
+        let opts = Opts::new(self.name, self.help).namespace("dish");
+
+        let metric = match self.kind {
+            Kind::Gauge(_) => Instance::Gauge(Gauge::with_opts(opts)?),
+            Kind::Counter(_) => Instance::Counter(
+                Counter::with_opts(opts)?,
+                Gauge::with_opts(
+                    Opts::new(
+                        format!("{}_created", self.name),
+                        format!("Unix time the count of {} started at.", self.name),
+                    )
+                    .namespace("dish"),
+                )?,
+            ),
+            Kind::Wedges(_) => Instance::GaugeVec(GaugeVec::new(opts, &["wedge"])?),
+            Kind::Info(labels, _) => Instance::GaugeVec(GaugeVec::new(opts, labels)?),
+        };
 
-        debug!("sending GetStatusRequest to Starlink device");
-        let req = tonic::Request::new(Request {
-            request: Some(request::Request::GetStatus(GetStatusRequest {})),
-            ..Default::default()
-        });
-        let res = client.handle(req).await?;
-        debug!("received gRPC response: {:#?}", &res);
-        let get_status_res = res.into_inner();
+        Ok(DishMetric { def: self, metric })
+    }
+}
 
-        if let Some(response::Response::DishGetStatus(response)) = get_status_res.response {
-            if let Some(device_info) = response.device_info {
-                let mut labels = HashMap::new();
+impl fmt::Debug for MetricDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricDef")
+            .field("name", &self.name)
+            .field("type", &self.type_name())
+            .field("unit", &self.unit)
+            .field("group", &self.group)
+            .finish()
+    }
+}
 
-                // `id` & `hardware_version` are set on program start to all metrics on the register level
+#[derive(Debug)]
+pub struct Metrics {
+    pub up: Gauge,
+    pub reboots: Counter,
+    pub last_reboot_time: Gauge,
 
-                // let id: String;
-                // if let Some(d_id) = device_info.id {
-                //     id = d_id;
+    dish: Vec<DishMetric>,
+    /// Status of the previous poll, to detect reboots.
+    previous: Option<Status>,
+}
 
-                //     info!("id: {}", &id);
+#[derive(Debug)]
+struct DishMetric {
+    def: &'static MetricDef,
+    metric: Instance,
+}
 
-                //     labels.insert("id", id.as_str());
-                // }
+#[derive(Debug)]
+enum Instance {
+    Gauge(Gauge),
+    /// The counter and its `_created` gauge.
+    Counter(Counter, Gauge),
+    GaugeVec(GaugeVec),
+}
 
-                // let hardware_version: String;
-                // if let Some(d_hardware_version) = device_info.hardware_version {
-                //     hardware_version = d_hardware_version;
+impl Metrics {
+    pub fn new() -> Result<Self, Error> {
+        let metrics = Metrics {
+            up: Gauge::with_opts(Opts::new("up", "Whether the last poll of the dish succeeded."))?,
+            reboots: Counter::with_opts(Opts::new(
+                "dish_reboots_total",
+                "Reboots of the dish detected by the exporter, from its uptime decreasing or its state changing to \
+                 booting.",
+            ))?,
+            last_reboot_time: Gauge::with_opts(Opts::new(
+                "dish_last_reboot_time_seconds",
+                "Unix time of the last reboot of the dish detected by the exporter.",
+            ))?,
+
+            dish: DISH_METRICS.iter().map(MetricDef::build).collect::<Result<_, _>>()?,
+            previous: None,
+        };
 
-                //     info!("hardware_version: {}", &hardware_version);
+        Ok(metrics)
+    }
 
-                //     labels.insert("hardware_version", hardware_version.as_str());
-                // }
+    pub fn register(&self, registry: &Registry, disabled: &[Group]) -> Result<(), Error> {
+        for collector in self.collectors(disabled) {
+            registry.register(collector)?;
+        }
 
-                let software_version: String;
-                if let Some(d_software_version) = device_info.software_version {
-                    software_version = d_software_version;
+        Ok(())
+    }
 
-                    info!("software_version: {}", &software_version);
+    /// All metrics except those in `disabled` groups.
+    pub fn collectors(&self, disabled: &[Group]) -> Vec<Box<dyn Collector>> {
+        let mut collectors: Vec<Box<dyn Collector>> = vec![
+            Box::new(self.up.clone()),
+            Box::new(self.reboots.clone()),
+            Box::new(self.last_reboot_time.clone()),
+        ];
+
+        for m in &self.dish {
+            if matches!(m.def.group, Some(group) if disabled.contains(&group)) {
+                continue;
+            }
 
-                    labels.insert("software_version", software_version.as_str());
-                }
+            match &m.metric {
+                Instance::Gauge(gauge) => collectors.push(Box::new(gauge.clone())),
+                Instance::Counter(counter, created) => {
+                    collectors.push(Box::new(counter.clone()));
+                    collectors.push(Box::new(created.clone()));
+                },
+                Instance::GaugeVec(gauge_vec) => collectors.push(Box::new(gauge_vec.clone())),
+            }
+        }
 
-                let country_code: String;
-                if let Some(d_country_code) = device_info.country_code {
-                    country_code = d_country_code;
+        collectors
+    }
 
-                    info!("country_code: {}", &country_code);
+    pub async fn update(&mut self, dish: &Dish) -> Result<Status, Error> {
+        debug!("updating metrics from Starlink device");
 
-                    labels.insert("country_code", country_code.as_str());
-                }
+        let mut status = Status::default();
 
-                self.device_info.get_metric_with(&labels)?.set(1_f64);
-            }
+        if let Some(response) = dish.get_status().await? {
+            status = self.observe(&response)?;
+        }
 
-            if let Some(device_state) = response.device_state {
-                if let Some(uptime_s) = device_state.uptime_s {
-                    info!("uptime_s: {}", &uptime_s);
+        debug!("updated metrics from Starlink device");
+        trace!("{:#?}", &self);
 
-                    let previous_uptime_s = self.uptime_s.get();
-                    if previous_uptime_s < uptime_s as f64 {
-                        self.uptime_s.inc_by(uptime_s as f64 - previous_uptime_s);
-                    } else if previous_uptime_s > uptime_s as f64 {
-                        self.uptime_s.reset();
-                        self.uptime_s.inc_by(uptime_s as f64);
-                    }
-                }
-            }
+        Ok(status)
+    }
 
-            if let Some(state) = response.state {
-                info!("state: {}", &state);
+    fn observe(&mut self, response: &DishGetStatusResponse) -> Result<Status, Error> {
+        let status = Status::from(response);
 
-                self.state.set(state as f64);
-            }
+        // `id` & `hardware_version` are set on program start to all metrics on the register level
 
-            if let Some(alerts) = response.alerts {
-                if let Some(motors_stuck) = alerts.motors_stuck {
-                    info!("alert_motors_stuck: {}", &motors_stuck);
-
-                    self.alert_motors_stuck.set(bool_to_f64(motors_stuck));
-                }
-                if let Some(thermal_throttle) = alerts.thermal_throttle {
-                    info!("alert_thermal_throttle: {}", &thermal_throttle);
-
-                    self.alert_thermal_throttle.set(bool_to_f64(thermal_throttle));
-                }
-                if let Some(thermal_shutdown) = alerts.thermal_shutdown {
-                    info!("alert_thermal_shutdown: {}", &thermal_shutdown);
-
-                    self.alert_thermal_shutdown.set(bool_to_f64(thermal_shutdown));
-                }
-                if let Some(mast_not_near_vertical) = alerts.mast_not_near_vertical {
-                    info!("alert_mast_not_near_vertical: {}", &mast_not_near_vertical);
-
-                    self.alert_mast_not_near_vertical
-                        .set(bool_to_f64(mast_not_near_vertical));
-                }
-                if let Some(unexpected_location) = alerts.unexpected_location {
-                    info!("alert_unexpected_location: {}", &unexpected_location);
-
-                    self.alert_unexpected_location.set(bool_to_f64(unexpected_location));
-                }
-                if let Some(slow_ethernet_speeds) = alerts.slow_ethernet_speeds {
-                    info!("alert_slow_ethernet_speeds: {}", &slow_ethernet_speeds);
-
-                    self.alert_slow_ethernet_speeds.set(bool_to_f64(slow_ethernet_speeds));
-                }
-            }
+        for m in &self.dish {
+            m.update(response)?;
+        }
 
-            if let Some(snr) = response.snr {
-                info!("snr: {}", &snr);
+        if matches!(&self.previous, Some(previous) if status.rebooted_since(previous)) {
+            self.reboots.inc();
+            // the boot time is more accurate than the time of the poll, which may have been a while later
+            let uptime = status.uptime_s.unwrap_or_default() as f64;
+            self.last_reboot_time.set(unix_time() as f64 - uptime);
+        }
+        self.previous = Some(status.clone());
 
-                self.snr.set(snr as f64);
-            }
+        Ok(status)
+    }
+}
 
-            if let Some(seconds_to_first_nonempty_slot) = response.seconds_to_first_nonempty_slot {
-                info!("seconds_to_first_nonempty_slot: {}", &seconds_to_first_nonempty_slot);
+impl DishMetric {
+    fn update(&self, response: &DishGetStatusResponse) -> Result<(), Error> {
+        let name = self.def.name;
+
+        match (self.def.kind, &self.metric) {
+            (Kind::Gauge(value), Instance::Gauge(gauge)) =>
+                if let Some(value) = value(response) {
+                    debug!("{}: {}", name, &value);
+
+                    gauge.set(value);
+                },
+            (Kind::Counter(value), Instance::Counter(counter, created)) =>
+                if let Some(value) = value(response) {
+                    debug!("{}: {}", name, &value);
+
+                    let previous = counter.get();
+                    // the count starts over on a decrease, and the first value is taken over as a whole as well
+                    if previous > value || created.get() == 0_f64 {
+                        counter.reset();
+                        counter.inc_by(value);
+                        created.set(unix_time() as f64 - value);
+                    } else if previous < value {
+                        counter.inc_by(value - previous);
+                    }
+                },
+            (Kind::Wedges(values), Instance::GaugeVec(gauge_vec)) =>
+                for (i, v) in values(response).into_iter().flatten().enumerate() {
+                    trace!("{}: wedge {}: {}", name, &i, &v);
+
+                    gauge_vec
+                        .get_metric_with_label_values(&[&i.to_string()])?
+                        .set(*v as f64);
+                },
+            (Kind::Info(_, values), Instance::GaugeVec(gauge_vec)) =>
+                if let Some(values) = values(response) {
+                    debug!("{}: {:?}", name, &values);
+
+                    let values = values.iter().map(String::as_str).collect::<Vec<_>>();
+                    gauge_vec.get_metric_with_label_values(&values)?.set(1_f64);
+                },
+            _ => unreachable!("metric {} built for a different kind", name),
+        }
 
-                self.seconds_to_first_nonempty_slot
-                    .set(seconds_to_first_nonempty_slot as f64);
-            }
+        Ok(())
+    }
+}
 
-            if let Some(pop_ping_drop_rate) = response.pop_ping_drop_rate {
-                info!("pop_ping_drop_rate: {}", &pop_ping_drop_rate);
+impl FromStr for Group {
+    type Err = Error;
 
-                self.pop_ping_drop_rate.set(pop_ping_drop_rate as f64);
-            }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "device_info" => Ok(Group::DeviceInfo),
+            "alerts" => Ok(Group::Alerts),
+            "obstruction" => Ok(Group::Obstruction),
+            "wedges" => Ok(Group::Wedges),
+            _ => Err(Error::Config(format!("unknown metric group {}", s))),
+        }
+    }
+}
 
-            if let Some(downlink_throughput_bps) = response.downlink_throughput_bps {
-                info!("downlink_throughput_bps: {}", &downlink_throughput_bps);
+fn bool_to_f64(v: bool) -> f64 {
+    match v {
+        true => 1_f64,
+        false => 0_f64,
+    }
+}
 
-                self.downlink_throughput_bps.set(downlink_throughput_bps as f64);
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlink::proto::space_x::api::device::{DeviceState, DishObstructionStats};
+
+    fn response(uptime_s: u64, valid_s: Option<f32>) -> DishGetStatusResponse {
+        DishGetStatusResponse {
+            device_state: Some(DeviceState {
+                uptime_s: Some(uptime_s),
+            }),
+            obstruction_stats: valid_s.map(|valid_s| DishObstructionStats {
+                valid_s: Some(valid_s),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
 
-            if let Some(uplink_throughput_bps) = response.uplink_throughput_bps {
-                info!("uplink_throughput_bps: {}", &uplink_throughput_bps);
+    fn dish_metric<'a>(metrics: &'a Metrics, name: &str) -> &'a Instance {
+        &metrics.dish.iter().find(|m| m.def.name == name).unwrap().metric
+    }
 
-                self.uplink_throughput_bps.set(uplink_throughput_bps as f64);
-            }
+    /// Value and `_created` of a counter.
+    fn counter(metrics: &Metrics, name: &str) -> (f64, f64) {
+        match dish_metric(metrics, name) {
+            Instance::Counter(counter, created) => (counter.get(), created.get()),
+            _ => panic!("{} isn't a counter", name),
+        }
+    }
 
-            if let Some(pop_ping_latency_ms) = response.pop_ping_latency_ms {
-                info!("pop_ping_latency_ms: {}", &pop_ping_latency_ms);
+    fn gauge(metrics: &Metrics, name: &str) -> f64 {
+        match dish_metric(metrics, name) {
+            Instance::Gauge(gauge) => gauge.get(),
+            _ => panic!("{} isn't a gauge", name),
+        }
+    }
 
-                self.pop_ping_latency_ms.set(pop_ping_latency_ms as f64);
-            }
+    /// Asserts `time` to be `seconds` before now, allowing for the clock to advance by a second during the test.
+    fn assert_ago(time: f64, seconds: f64) {
+        let ago = unix_time() as f64 - time;
+        assert!(
+            ago >= seconds && ago <= seconds + 1_f64,
+            "{} is {} seconds ago",
+            time,
+            ago
+        );
+    }
 
-            if let Some(obstruction_stats) = response.obstruction_stats {
-                if let Some(currently_obstructed) = obstruction_stats.currently_obstructed {
-                    info!("obstruction_currently_obstructed: {}", &currently_obstructed);
-
-                    self.obstruction_currently_obstructed
-                        .set(bool_to_f64(currently_obstructed));
-                }
-                if let Some(fraction_obstructed) = obstruction_stats.fraction_obstructed {
-                    info!("obstruction_fraction_obstructed: {}", &fraction_obstructed);
-
-                    self.obstruction_fraction_obstructed.set(fraction_obstructed as f64);
-                }
-                if let Some(last_24h_obstructed_s) = obstruction_stats.last_24h_obstructed_s {
-                    info!("obstruction_last_24h_obstructed_s: {}", &last_24h_obstructed_s);
-
-                    let previous_obstruction_last_24h_obstructed_s = self.obstruction_last_24h_obstructed_s.get();
-                    if previous_obstruction_last_24h_obstructed_s < last_24h_obstructed_s as f64 {
-                        self.obstruction_last_24h_obstructed_s
-                            .inc_by(last_24h_obstructed_s as f64 - previous_obstruction_last_24h_obstructed_s);
-                    } else if previous_obstruction_last_24h_obstructed_s > last_24h_obstructed_s as f64 {
-                        self.obstruction_last_24h_obstructed_s.reset();
-                        self.obstruction_last_24h_obstructed_s
-                            .inc_by(last_24h_obstructed_s as f64);
-                    }
-                }
-                if let Some(valid_s) = obstruction_stats.valid_s {
-                    info!("obstruction_valid_s: {}", &valid_s);
-
-                    let previous_obstruction_valid_s = self.obstruction_valid_s.get();
-                    if previous_obstruction_valid_s < valid_s as f64 {
-                        self.obstruction_valid_s
-                            .inc_by(valid_s as f64 - previous_obstruction_valid_s);
-                    } else if previous_obstruction_valid_s > valid_s as f64 {
-                        self.obstruction_valid_s.reset();
-                        self.obstruction_valid_s.inc_by(valid_s as f64);
-                    }
-                }
-
-                for (i, v) in obstruction_stats.wedge_fraction_obstructed.into_iter().enumerate() {
-                    info!("obstruction_wedge_fraction_obstructed: wedge {}: {}", &i, &v);
-
-                    let mut m = HashMap::new();
-                    let i = format!("{}", i);
-                    m.insert("wedge", i.as_str());
-
-                    self.obstruction_wedge_fraction_obstructed
-                        .get_metric_with(&m)?
-                        .set(v as f64);
-                }
-                for (i, v) in obstruction_stats.wedge_abs_fraction_obstructed.into_iter().enumerate() {
-                    info!("obstruction_wedge_abs_fraction_obstructed: wedge {}: {}", &i, &v);
-
-                    let mut m = HashMap::new();
-                    let i = format!("{}", i);
-                    m.insert("wedge", i.as_str());
-
-                    self.obstruction_wedge_abs_fraction_obstructed
-                        .get_metric_with(&m)?
-                        .set(v as f64);
-                }
-            }
-        }
+    #[test]
+    fn follows_counters() {
+        let mut metrics = Metrics::new().unwrap();
 
-        info!("updated metrics from Starlink device");
-        debug!("{:#?}", &self);
+        metrics.observe(&response(100, Some(50_f32))).unwrap();
+        let (uptime, created) = counter(&metrics, "uptime_s");
+        assert_eq!(uptime, 100_f64);
+        assert_ago(created, 100_f64);
+        let (valid, valid_created) = counter(&metrics, "obstruction_valid_s");
+        assert_eq!(valid, 50_f64);
 
-        Ok(())
+        metrics.observe(&response(160, Some(80_f32))).unwrap();
+        assert_eq!(counter(&metrics, "uptime_s"), (160_f64, created));
+        assert_eq!(counter(&metrics, "obstruction_valid_s"), (80_f64, valid_created));
+
+        // missing values keep the count
+        metrics.observe(&response(170, None)).unwrap();
+        assert_eq!(counter(&metrics, "obstruction_valid_s"), (80_f64, valid_created));
     }
-}
 
-fn bool_to_f64(v: bool) -> f64 {
-    match v {
-        true => 1_f64,
-        false => 0_f64,
+    #[test]
+    fn resets_counters_on_decrease() {
+        let mut metrics = Metrics::new().unwrap();
+
+        metrics.observe(&response(1000, Some(900_f32))).unwrap();
+        metrics.observe(&response(20, Some(10_f32))).unwrap();
+
+        let (uptime, created) = counter(&metrics, "uptime_s");
+        assert_eq!(uptime, 20_f64);
+        assert_ago(created, 20_f64);
+        let (valid, created) = counter(&metrics, "obstruction_valid_s");
+        assert_eq!(valid, 10_f64);
+        assert_ago(created, 10_f64);
+    }
+
+    #[test]
+    fn derives_boot_time() {
+        let mut metrics = Metrics::new().unwrap();
+
+        metrics.observe(&response(1000, None)).unwrap();
+        assert_ago(gauge(&metrics, "boot_time_seconds"), 1000_f64);
+        assert_eq!(metrics.reboots.get(), 0_f64);
+        assert_eq!(metrics.last_reboot_time.get(), 0_f64);
+
+        metrics.observe(&response(1010, None)).unwrap();
+        assert_ago(gauge(&metrics, "boot_time_seconds"), 1010_f64);
+        assert_eq!(metrics.reboots.get(), 0_f64);
+
+        // the uptime decreasing is a reboot
+        metrics.observe(&response(30, None)).unwrap();
+        assert_ago(gauge(&metrics, "boot_time_seconds"), 30_f64);
+        assert_eq!(metrics.reboots.get(), 1_f64);
+        assert_ago(metrics.last_reboot_time.get(), 30_f64);
     }
 }
@@ -0,0 +1,80 @@
+//! Dish serving the `Handle` method of the device service for tests, as the `starlink` crate only includes the client.
+
+use prost::Message;
+use starlink::proto::space_x::api::device::{response, Request, Response};
+use starlink_exporter::dish::Dish;
+use std::{
+    convert::Infallible,
+    future,
+    sync::{Arc, Mutex},
+};
+use warp::{
+    http::{self, HeaderMap},
+    hyper::{
+        body,
+        service::{make_service_fn, service_fn},
+        Body,
+        Server,
+    },
+};
+
+/// Requests received by the mock dish.
+pub type Requests = Arc<Mutex<Vec<Request>>>;
+
+/// Serves plaintext HTTP/2 on a local port, answering every request with `respond` and recording it. Returns a dish
+/// connected to it.
+pub async fn serve(respond: fn(&Request) -> response::Response) -> (Dish, Requests) {
+    let requests = Requests::default();
+
+    let make_service = {
+        let requests = requests.clone();
+
+        make_service_fn(move |_| {
+            let requests = requests.clone();
+
+            future::ready(Ok::<_, Infallible>(service_fn(move |req: http::Request<Body>| {
+                let requests = requests.clone();
+
+                async move {
+                    // gRPC messages are prefixed by a compression flag and their length
+                    let bytes = body::to_bytes(req.into_body()).await.unwrap();
+                    let request = Request::decode(&bytes[5..]).unwrap();
+
+                    let message = Response {
+                        response: Some(respond(&request)),
+                        ..Default::default()
+                    }
+                    .encode_to_vec();
+                    requests.lock().unwrap().push(request);
+
+                    let mut frame = vec![0];
+                    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+                    frame.extend_from_slice(&message);
+
+                    let (mut sender, body) = Body::channel();
+                    tokio::spawn(async move {
+                        let mut trailers = HeaderMap::new();
+                        trailers.insert("grpc-status", "0".parse().unwrap());
+
+                        sender.send_data(frame.into()).await.unwrap();
+                        sender.send_trailers(trailers).await.unwrap();
+                    });
+
+                    http::Response::builder()
+                        .header("content-type", "application/grpc")
+                        .body(body)
+                }
+            })))
+        })
+    };
+
+    let server = Server::bind(&([127, 0, 0, 1], 0).into())
+        .http2_only(true)
+        .serve(make_service);
+    let address = server.local_addr();
+    tokio::spawn(server);
+
+    let dish = Dish::connect(format!("http://{}", address)).await.unwrap();
+
+    (dish, requests)
+}
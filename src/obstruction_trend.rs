@@ -0,0 +1,410 @@
+use futures_util::{Stream, StreamExt};
+use prometheus::{GaugeVec, Opts, Registry};
+use serde::{Deserialize, Serialize};
+use starlink_exporter::{status::Status, util::unix_time};
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::task;
+use tracing::{error, info};
+
+use crate::{
+    events::Event,
+    exporter_error::Error,
+    exporter_metrics::ExporterMetrics,
+    poller::Poller,
+    shutdown::Shutdown,
+};
+
+/// Days before the current one the baseline of a wedge is averaged over.
+const BASELINE_DAYS: i64 = 30;
+
+/// Days with samples of a wedge required before its baseline is used.
+const MIN_BASELINE_DAYS: usize = 7;
+
+/// Longest time between saves of the state, besides saving on changes of day, raised wedges and shutdown.
+const SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Baseline of the obstruction of every wedge around the dish, from the daily means of
+/// `obstruction_wedge_fraction_obstructed` over the previous 30 days, and the change of the latest value from it.
+///
+/// Publishes an `ObstructionIncreased` event once the change of a wedge reaches the threshold, and again only after it
+/// fell below half of it. The state is persisted to a JSON file, so the baseline survives restarts.
+#[derive(Debug)]
+pub struct ObstructionTrend {
+    path: PathBuf,
+    /// Change of a wedge from its baseline raising an event.
+    threshold: f64,
+    exporter_metrics: ExporterMetrics,
+    state: State,
+    last_save: Instant,
+
+    wedge_baseline: GaugeVec,
+    wedge_change: GaugeVec,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// Sums of the values of every wedge per UTC day, oldest first, the last one being the current day.
+    days: VecDeque<Day>,
+    /// Wedges whose increase was published and hasn't receded since.
+    raised: BTreeSet<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Day {
+    /// Days since the Unix epoch.
+    day: i64,
+    sums: Vec<f64>,
+    counts: Vec<u64>,
+}
+
+impl Day {
+    fn mean(&self, wedge: usize) -> Option<f64> {
+        match (self.sums.get(wedge), self.counts.get(wedge)) {
+            (Some(sum), Some(count)) if *count > 0 => Some(sum / *count as f64),
+            _ => None,
+        }
+    }
+}
+
+impl ObstructionTrend {
+    /// Loads the state from `path`, starting without a baseline if the file doesn't exist yet.
+    pub fn open(path: PathBuf, threshold: f64, exporter_metrics: ExporterMetrics) -> Result<Self, Error> {
+        let state = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(io::Error::from)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => State::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let trend = ObstructionTrend {
+            path,
+            threshold,
+            exporter_metrics,
+            state,
+            last_save: Instant::now(),
+
+            wedge_baseline: GaugeVec::new(
+                Opts::new(
+                    "dish_obstruction_wedge_baseline",
+                    "Obstruction: Wedge fraction obstructed, averaged over the daily means of the previous 30 days.",
+                ),
+                &["wedge"],
+            )?,
+            wedge_change: GaugeVec::new(
+                Opts::new(
+                    "dish_obstruction_wedge_change",
+                    "Obstruction: Change of the wedge fraction obstructed from its baseline. Absent until 7 days of \
+                     baseline were recorded.",
+                ),
+                &["wedge"],
+            )?,
+        };
+
+        Ok(trend)
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<(), Error> {
+        registry.register(Box::new(self.wedge_baseline.clone()))?;
+        registry.register(Box::new(self.wedge_change.clone()))?;
+
+        Ok(())
+    }
+
+    /// Compares the wedges to their baseline on every poll and saves the state until the `statuses` end. `shutdown`
+    /// is held until the state is saved.
+    pub async fn run(mut self, poller: Arc<Poller>, statuses: impl Stream<Item = Status>, shutdown: Shutdown) {
+        tokio::pin!(statuses);
+
+        while let Some(status) = statuses.next().await {
+            let changed = self.observe(&status, &poller, unix_time());
+            if changed || self.last_save.elapsed() >= SAVE_INTERVAL {
+                self.save().await;
+            }
+        }
+
+        self.save().await;
+        drop(shutdown);
+    }
+
+    /// Adds the wedges of `status` to the current day and publishes the wedges whose change reached the threshold.
+    /// Returns whether the day or the raised wedges changed.
+    fn observe(&mut self, status: &Status, poller: &Poller, now: i64) -> bool {
+        let today = now.div_euclid(24 * 60 * 60);
+        let mut changed = false;
+
+        // samples of earlier days after the clock changed are added to the current day
+        if self.state.days.back().map(|d| d.day < today).unwrap_or(true) {
+            self.state.days.push_back(Day {
+                day: today,
+                sums: vec![],
+                counts: vec![],
+            });
+            while matches!(self.state.days.front(), Some(day) if day.day < today - BASELINE_DAYS) {
+                self.state.days.pop_front();
+            }
+            changed = true;
+        }
+
+        let wedges = &status.obstruction.wedge_fraction_obstructed;
+        let current = self.state.days.back_mut().expect("pushed current day");
+        if current.sums.len() < wedges.len() {
+            current.sums.resize(wedges.len(), 0_f64);
+            current.counts.resize(wedges.len(), 0);
+        }
+
+        for (wedge, value) in wedges.iter().enumerate().filter(|(_, v)| v.is_finite()) {
+            current.sums[wedge] += *value as f64;
+            current.counts[wedge] += 1;
+        }
+
+        for (wedge, value) in wedges.iter().enumerate().filter(|(_, v)| v.is_finite()) {
+            let value = *value as f64;
+            let label = wedge.to_string();
+            let baseline = match self.baseline(wedge) {
+                Some(baseline) => baseline,
+                None => {
+                    // fail if they weren't set before
+                    let _ = self.wedge_baseline.remove_label_values(&[&label]);
+                    let _ = self.wedge_change.remove_label_values(&[&label]);
+
+                    continue;
+                },
+            };
+            let change = value - baseline;
+            self.wedge_baseline.with_label_values(&[&label]).set(baseline);
+            self.wedge_change.with_label_values(&[&label]).set(change);
+
+            if change >= self.threshold && self.state.raised.insert(wedge) {
+                info!(wedge, baseline, fraction = value, "obstruction of wedge increased");
+
+                poller.publish(Event::ObstructionIncreased {
+                    wedge,
+                    baseline,
+                    fraction: value,
+                });
+                changed = true;
+            } else if change < self.threshold / 2_f64 && self.state.raised.remove(&wedge) {
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    /// Mean of the daily means of `wedge` before the current day.
+    fn baseline(&self, wedge: usize) -> Option<f64> {
+        let days = self.state.days.len().saturating_sub(1);
+        let means = self
+            .state
+            .days
+            .iter()
+            .take(days)
+            .filter_map(|day| day.mean(wedge))
+            .collect::<Vec<_>>();
+
+        (means.len() >= MIN_BASELINE_DAYS).then(|| means.iter().sum::<f64>() / means.len() as f64)
+    }
+
+    async fn save(&mut self) {
+        self.last_save = Instant::now();
+
+        let result = match serde_json::to_vec(&self.state) {
+            Ok(bytes) => {
+                let path = self.path.clone();
+                task::spawn_blocking(move || write(&path, &bytes))
+                    .await
+                    .map_err(io::Error::from)
+                    .and_then(|r| r)
+            },
+            Err(e) => Err(e.into()),
+        };
+
+        if let Err(e) = result {
+            let e = Error::from(e);
+            error!("saving obstruction trend to {}: {}", self.path.display(), e);
+            self.exporter_metrics.observe_error(&e);
+        }
+    }
+}
+
+/// Replaces the file at `path`, so it's never left half-written.
+fn write(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlink_exporter::{dish::Dish, metrics::Metrics, status::Obstruction};
+    use tokio::sync::broadcast::Receiver;
+    use tonic::transport::Channel;
+
+    const DAY: i64 = 24 * 60 * 60;
+    /// Start of a UTC day.
+    const START: i64 = 19_000 * DAY;
+
+    fn trend(path: PathBuf) -> ObstructionTrend {
+        ObstructionTrend::open(path, 0.25, ExporterMetrics::new().unwrap()).unwrap()
+    }
+
+    fn poller() -> Poller {
+        let dish = Dish::from_channel(Channel::from_static("http://127.0.0.1:9200").connect_lazy());
+
+        Poller::new(
+            dish,
+            None,
+            false,
+            Metrics::new().unwrap(),
+            ExporterMetrics::new().unwrap(),
+        )
+    }
+
+    fn status(wedges: &[f32]) -> Status {
+        Status {
+            obstruction: Obstruction {
+                wedge_fraction_obstructed: wedges.to_vec(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn raised(events: &mut Receiver<Event>) -> Vec<usize> {
+        let mut raised = vec![];
+        while let Ok(event) = events.try_recv() {
+            if let Event::ObstructionIncreased { wedge, .. } = event {
+                raised.push(wedge);
+            }
+        }
+
+        raised
+    }
+
+    /// Observes `value` for wedge `0` once on each of the `days` starting with `START`.
+    fn observe_days(trend: &mut ObstructionTrend, poller: &Poller, days: i64, value: f32) {
+        for day in 0..days {
+            trend.observe(&status(&[value]), poller, START + day * DAY);
+        }
+    }
+
+    #[tokio::test]
+    async fn averages_daily_means() {
+        let mut trend = trend(PathBuf::from("unused"));
+        let poller = poller();
+
+        for day in 0..7 {
+            let time = START + day * DAY;
+            trend.observe(&status(&[0_f32, 1_f32]), &poller, time);
+            trend.observe(&status(&[0_f32, 1_f32]), &poller, time + 60);
+            trend.observe(&status(&[0.5, 1_f32]), &poller, time + 120);
+        }
+        // 6 days before the current one
+        assert_eq!(trend.baseline(0), None);
+        // fails if the gauge isn't set
+        assert!(trend.wedge_baseline.remove_label_values(&["0"]).is_err());
+
+        trend.observe(&status(&[0.5, 1_f32]), &poller, START + 7 * DAY);
+
+        assert_eq!(trend.baseline(0), Some(0.5 / 3_f64));
+        assert_eq!(trend.wedge_baseline.with_label_values(&["0"]).get(), 0.5 / 3_f64);
+        assert_eq!(trend.wedge_change.with_label_values(&["0"]).get(), 0.5 - 0.5 / 3_f64);
+        assert_eq!(trend.wedge_change.with_label_values(&["1"]).get(), 0_f64);
+    }
+
+    #[tokio::test]
+    async fn raises_at_threshold_until_below_half() {
+        let mut trend = trend(PathBuf::from("unused"));
+        let poller = poller();
+        let mut events = poller.subscribe();
+        observe_days(&mut trend, &poller, 7, 0.25);
+        let now = START + 7 * DAY;
+
+        for (value, changed, raised_wedges) in [
+            // new day
+            (0.25, true, vec![]),
+            (0.5, true, vec![0]),
+            (0.5, false, vec![]),
+            (0.375, false, vec![]),
+            (0.25, true, vec![]),
+            (0.5, true, vec![0]),
+        ] {
+            assert_eq!(trend.observe(&status(&[value]), &poller, now), changed, "{}", value);
+            assert_eq!(raised(&mut events), raised_wedges, "{}", value);
+        }
+        assert_eq!(trend.state.raised, BTreeSet::from([0]));
+    }
+
+    #[tokio::test]
+    async fn rolls_over_days() {
+        let mut trend = trend(PathBuf::from("unused"));
+        let poller = poller();
+
+        assert!(trend.observe(&status(&[1_f32]), &poller, START));
+        assert!(!trend.observe(&status(&[1_f32]), &poller, START + DAY - 1));
+        assert!(trend.observe(&status(&[1_f32]), &poller, START + DAY));
+        // the clock went back
+        assert!(!trend.observe(&status(&[1_f32]), &poller, START));
+
+        let days = trend
+            .state
+            .days
+            .iter()
+            .map(|d| (d.day, d.counts.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(days, [(START / DAY, vec![2]), (START / DAY + 1, vec![2])]);
+    }
+
+    #[tokio::test]
+    async fn evicts_days_older_than_baseline() {
+        let mut trend = trend(PathBuf::from("unused"));
+        let poller = poller();
+        trend.observe(&status(&[1_f32]), &poller, START);
+        for day in 1..BASELINE_DAYS {
+            trend.observe(&status(&[0_f32]), &poller, START + day * DAY);
+        }
+
+        trend.observe(&status(&[0_f32]), &poller, START + BASELINE_DAYS * DAY);
+        assert_eq!(trend.state.days.len(), BASELINE_DAYS as usize + 1);
+        assert_eq!(trend.baseline(0), Some(1_f64 / BASELINE_DAYS as f64));
+
+        trend.observe(&status(&[0_f32]), &poller, START + (BASELINE_DAYS + 1) * DAY);
+        assert_eq!(trend.state.days.len(), BASELINE_DAYS as usize + 1);
+        assert_eq!(trend.state.days.front().map(|d| d.day), Some(START / DAY + 1));
+        assert_eq!(trend.baseline(0), Some(0_f64));
+    }
+
+    #[tokio::test]
+    async fn saves_and_reopens_state() {
+        let dir = std::env::temp_dir().join(format!("starlink-exporter-obstruction-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("obstruction.json");
+        let poller = poller();
+
+        let mut saved = trend(path.clone());
+        assert!(saved.state.days.is_empty());
+        observe_days(&mut saved, &poller, 7, 0.25);
+        saved.observe(&status(&[0.5]), &poller, START + 7 * DAY);
+        saved.save().await;
+
+        let reopened = trend(path);
+        assert_eq!(
+            serde_json::to_value(&reopened.state).unwrap(),
+            serde_json::to_value(&saved.state).unwrap()
+        );
+        assert_eq!(reopened.state.raised, BTreeSet::from([0]));
+        assert_eq!(reopened.baseline(0), Some(0.25));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
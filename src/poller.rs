@@ -0,0 +1,405 @@
+use futures_util::{future, Stream, StreamExt};
+use prometheus::Gauge;
+use starlink_exporter::{dish::Dish, metrics::Metrics, status::Status, util::unix_time};
+use std::{
+    io,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+    sync::{broadcast, Mutex},
+    time::{self, MissedTickBehavior},
+};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
+
+use crate::{
+    events::{self, Event},
+    exporter_error::Error,
+    exporter_metrics::ExporterMetrics,
+    history::HistoryCursor,
+    shutdown::Shutdown,
+    systemd,
+};
+
+/// Capacity of the event channel. Subscribers lagging further behind miss events.
+const EVENT_CAPACITY: usize = 64;
+
+/// Owns the dish `Metrics` and publishes an `Event` stream of every update to them, optionally followed by the history
+/// samples the dish recorded since the previous poll.
+#[derive(Debug)]
+pub struct Poller {
+    dish: Dish,
+    dish_id: Option<String>,
+    log_reboots: bool,
+    /// Whether to read the history after every poll.
+    history: bool,
+    exporter_metrics: ExporterMetrics,
+    /// `up` of the dish metrics, settable without waiting for a poll in progress.
+    up: Gauge,
+    inner: Mutex<Inner>,
+    info: RwLock<PollInfo>,
+    events: broadcast::Sender<Event>,
+}
+
+/// Outcome of the latest polls, readable without waiting for a poll in progress.
+#[derive(Debug, Clone, Default)]
+pub struct PollInfo {
+    pub last_attempt: Option<SystemTime>,
+    pub last_success: Option<SystemTime>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    metrics: Metrics,
+    last_status: Option<Status>,
+    cursor: HistoryCursor,
+}
+
+impl Poller {
+    pub fn new(
+        dish: Dish,
+        dish_id: Option<String>,
+        log_reboots: bool,
+        metrics: Metrics,
+        exporter_metrics: ExporterMetrics,
+    ) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+
+        Poller {
+            dish,
+            dish_id,
+            log_reboots,
+            history: false,
+            exporter_metrics,
+            up: metrics.up.clone(),
+            inner: Mutex::new(Inner {
+                metrics,
+                last_status: None,
+                cursor: HistoryCursor::default(),
+            }),
+            info: RwLock::new(PollInfo::default()),
+            events,
+        }
+    }
+
+    /// Reads the history of the dish after every successful poll and publishes its new samples as `Event::History`,
+    /// for all consumers of the history to share a single request.
+    pub fn with_history(self, history: bool) -> Self { Poller { history, ..self } }
+
+    pub fn starlink_address(&self) -> Option<&str> { self.dish.address() }
+
+    pub fn info(&self) -> PollInfo { self.info.read().expect("reading poll info").clone() }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> { self.events.subscribe() }
+
+    /// Events published from now on, ending on shutdown. Events missed by a lagging `subscriber` are skipped with a
+    /// warning naming it.
+    pub fn events(&self, subscriber: &'static str, shutdown: Shutdown) -> impl Stream<Item = Event> {
+        BroadcastStream::new(self.subscribe())
+            .filter_map(move |event| {
+                future::ready(match event {
+                    Ok(event) => Some(event),
+                    Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                        warn!("{} missed {} events", subscriber, missed);
+
+                        None
+                    },
+                })
+            })
+            .take_until(shutdown.wait())
+    }
+
+    /// Statuses of the polls from now on, ending on shutdown. See `events`.
+    pub fn statuses(&self, subscriber: &'static str, shutdown: Shutdown) -> impl Stream<Item = Status> {
+        self.events(subscriber, shutdown).filter_map(|event| {
+            future::ready(match event {
+                Event::Status(status) => Some(status),
+                _ => None,
+            })
+        })
+    }
+
+    /// Publishes an event derived outside of the poller to the subscribers.
+    pub fn publish(&self, event: Event) {
+        // sending only fails if there are no subscribers
+        let _ = self.events.send(event);
+    }
+
+    pub async fn last_status(&self) -> Option<Status> { self.inner.lock().await.last_status.clone() }
+
+    /// Updates the metrics from the dish and publishes the resulting events. `timeout` overrides the deadline of the
+    /// requests to the dish, including the time spent waiting for a poll in progress.
+    pub async fn poll(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        let span = info_span!("poll", dish_id = self.dish_id.as_deref(), duration_ms = field::Empty);
+
+        self.poll_in_span(timeout).instrument(span).await
+    }
+
+    async fn poll_in_span(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        // waiting for a poll in progress counts towards the deadline as well
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut inner = match timeout {
+            Some(timeout) => match time::timeout(timeout, self.inner.lock()).await {
+                Ok(inner) => inner,
+                Err(_) => {
+                    let e = io::Error::new(io::ErrorKind::TimedOut, "waiting for the poll in progress").into();
+                    self.fail(&e);
+
+                    return Err(e);
+                },
+            },
+            None => self.inner.lock().await,
+        };
+        let start = Instant::now();
+
+        self.info.write().expect("writing poll info").last_attempt = Some(SystemTime::now());
+
+        let result = match deadline {
+            // the dish may not honor the timeout sent along with the requests
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(start);
+                let dish = self.dish.clone().with_timeout(timeout);
+                time::timeout(timeout, inner.metrics.update(&dish))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(tonic::Status::deadline_exceeded("polling Starlink device timed out").into())
+                    })
+            },
+            None => inner.metrics.update(&self.dish).await,
+        }
+        .map_err(Error::from);
+
+        Span::current().record("duration_ms", start.elapsed().as_millis() as u64);
+
+        let status = match result {
+            Ok(status) => {
+                self.up.set(1_f64);
+                systemd::notify_watchdog();
+                debug!("polled Starlink device");
+                self.exporter_metrics.polls_total.with_label_values(&["success"]).inc();
+
+                let mut info = self.info.write().expect("writing poll info");
+                info.last_success = info.last_attempt;
+                info.last_error = None;
+
+                status
+            },
+            Err(e) => {
+                self.fail(&e);
+
+                return Err(e);
+            },
+        };
+
+        // sending only fails if there are no subscribers
+        let _ = self.events.send(Event::Status(status.clone()));
+        for event in events::transitions(inner.last_status.as_ref(), &status) {
+            if let Event::Rebooted {
+                previous_uptime_s,
+                uptime_s,
+            } = event
+            {
+                if self.log_reboots {
+                    info!(previous_uptime_s, uptime_s, "Starlink device rebooted");
+                }
+            }
+
+            let _ = self.events.send(event);
+        }
+
+        inner.last_status = Some(status);
+
+        if self.history {
+            self.read_history(&mut inner, deadline).await;
+        }
+
+        Ok(())
+    }
+
+    fn fail(&self, e: &Error) {
+        self.up.set(0_f64);
+        self.exporter_metrics.polls_total.with_label_values(&["failure"]).inc();
+        self.exporter_metrics.observe_error(e);
+
+        let error = describe(e);
+        self.info.write().expect("writing poll info").last_error = Some(error.clone());
+        let _ = self.events.send(Event::PollFailed { error });
+    }
+
+    /// Publishes the history samples added since the previous poll. Failing to read them doesn't fail the poll, as the
+    /// next one reads the missed samples along with its own.
+    async fn read_history(&self, inner: &mut Inner, deadline: Option<Instant>) {
+        let result = match deadline {
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                let dish = self.dish.clone().with_timeout(timeout);
+                time::timeout(timeout, dish.get_history()).await.unwrap_or_else(|_| {
+                    Err(tonic::Status::deadline_exceeded("reading history of Starlink device timed out").into())
+                })
+            },
+            None => self.dish.get_history().await,
+        }
+        .map_err(Error::from);
+
+        match result {
+            Ok(Some(history)) => {
+                let samples = inner.cursor.advance(&history, unix_time());
+                debug!("read {} history samples", samples.len());
+
+                let _ = self.events.send(Event::History(samples));
+            },
+            Ok(None) => {},
+            Err(e) => {
+                error!("reading history of Starlink device: {}", e);
+                self.exporter_metrics.observe_error(&e);
+            },
+        }
+    }
+
+    /// Polls the dish in the background on a fixed interval until shutdown. A poll in progress is finished first.
+    pub async fn run(self: Arc<Self>, interval: Duration, mut shutdown: Shutdown) {
+        let mut interval = time::interval(interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {},
+                _ = shutdown.triggered() => break,
+            }
+
+            if let Err(e) = self.poll(None).await {
+                error!("polling Starlink device: {}", e);
+            }
+        }
+    }
+}
+
+/// Formats an error with all its sources, as the top-level messages of `Error` don't carry any details.
+fn describe(e: &(dyn std::error::Error + 'static)) -> String {
+    let mut description = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        // some sources already include their own sources in their message
+        let message = e.to_string();
+        if !description.contains(&message) {
+            description = format!("{}: {}", description, message);
+        }
+        source = e.source();
+    }
+
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlink::proto::space_x::api::device::{
+        request,
+        response,
+        DishGetHistoryResponse,
+        DishGetStatusResponse,
+        Request,
+    };
+
+    use crate::{mock_dish, shutdown};
+
+    fn respond(request: &Request) -> response::Response {
+        match request.request {
+            Some(request::Request::GetHistory(_)) => response::Response::DishGetHistory(DishGetHistoryResponse {
+                current: Some(3),
+                pop_ping_drop_rate: vec![0_f32, 0.5, 1_f32],
+                ..Default::default()
+            }),
+            _ => response::Response::DishGetStatus(DishGetStatusResponse {
+                state: Some(1),
+                ..Default::default()
+            }),
+        }
+    }
+
+    async fn poll(history: bool) -> (Vec<Event>, usize) {
+        let (dish, requests) = mock_dish::serve(respond).await;
+        let exporter_metrics = ExporterMetrics::new().unwrap();
+        let poller = Poller::new(dish, None, false, Metrics::new().unwrap(), exporter_metrics).with_history(history);
+        let mut events = poller.subscribe();
+
+        poller.poll(Some(Duration::from_secs(5))).await.unwrap();
+        // the cursor only takes new samples
+        poller.poll(Some(Duration::from_secs(5))).await.unwrap();
+
+        let mut received = vec![];
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        let requests = requests.lock().unwrap().len();
+
+        (received, requests)
+    }
+
+    #[tokio::test]
+    async fn publishes_history_after_status() {
+        let (events, requests) = poll(true).await;
+
+        assert_eq!(requests, 4);
+        assert!(matches!(
+            &events[..],
+            [Event::Status(_), Event::StateChanged { .. }, Event::History(first), Event::Status(_), Event::History(second)]
+                if first.len() == 3 && second.is_empty()
+        ));
+    }
+
+    #[tokio::test]
+    async fn reads_history_only_if_enabled() {
+        let (events, requests) = poll(false).await;
+
+        assert_eq!(requests, 2);
+        assert!(!events.iter().any(|e| matches!(e, Event::History(_))));
+    }
+
+    #[tokio::test]
+    async fn fails_waiting_for_poll_in_progress() {
+        let (dish, _) = mock_dish::serve(respond).await;
+        let exporter_metrics = ExporterMetrics::new().unwrap();
+        let poller = Poller::new(dish, None, false, Metrics::new().unwrap(), exporter_metrics.clone());
+        poller.poll(Some(Duration::from_secs(5))).await.unwrap();
+        assert_eq!(poller.up.get(), 1_f64);
+
+        let inner = poller.inner.lock().await;
+        let result = poller.poll(Some(Duration::from_millis(50))).await;
+        drop(inner);
+
+        assert!(
+            matches!(result, Err(Error::Starlink(starlink_exporter::error::Error::Io(e))) if e.kind() == io::ErrorKind::TimedOut)
+        );
+        assert_eq!(poller.up.get(), 0_f64);
+        assert_eq!(
+            exporter_metrics.polls_total.with_label_values(&["failure"]).get(),
+            1_f64
+        );
+        assert!(poller
+            .info()
+            .last_error
+            .unwrap()
+            .contains("waiting for the poll in progress"));
+    }
+
+    #[tokio::test]
+    async fn streams_statuses_until_shutdown() {
+        let (dish, _) = mock_dish::serve(respond).await;
+        let exporter_metrics = ExporterMetrics::new().unwrap();
+        let poller = Poller::new(dish, None, false, Metrics::new().unwrap(), exporter_metrics).with_history(true);
+        let (trigger, shutdown) = shutdown::channel();
+        let statuses = poller.statuses("test", shutdown);
+        tokio::pin!(statuses);
+
+        poller.poll(Some(Duration::from_secs(5))).await.unwrap();
+        assert!(statuses.next().await.is_some());
+
+        // the trigger waits for the stream to drop its handle
+        let shutdown = tokio::spawn(trigger.shutdown());
+        assert!(statuses.next().await.is_none());
+        shutdown.await.unwrap();
+    }
+}
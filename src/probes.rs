@@ -0,0 +1,246 @@
+use prometheus::{Gauge, GaugeVec, Opts, Registry};
+use starlink_exporter::{dish::Dish, util::unix_time_f64};
+use std::time::Duration;
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{debug, error};
+
+use crate::{exporter_error::Error, exporter_metrics::ExporterMetrics, schedule::Schedule, shutdown::Shutdown};
+
+/// Deadline of speed tests, which take much longer than other requests to the dish.
+const SPEED_TEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Pings hosts from the dish, measuring the round trip beyond the Starlink PoP.
+#[derive(Debug)]
+pub struct PingProbe {
+    dish: Dish,
+    targets: Vec<String>,
+    exporter_metrics: ExporterMetrics,
+
+    rtt_seconds: GaugeVec,
+    drop_rate: GaugeVec,
+    timestamp_seconds: GaugeVec,
+}
+
+/// Runs speed tests from the dish.
+#[derive(Debug)]
+pub struct SpeedTestProbe {
+    dish: Dish,
+    exporter_metrics: ExporterMetrics,
+
+    download_bps: Gauge,
+    upload_bps: Gauge,
+    latency_seconds: Gauge,
+    timestamp_seconds: Gauge,
+}
+
+impl PingProbe {
+    pub fn new(dish: Dish, targets: Vec<String>, exporter_metrics: ExporterMetrics) -> Result<Self, Error> {
+        let probe = PingProbe {
+            dish,
+            targets,
+            exporter_metrics,
+
+            rtt_seconds: GaugeVec::new(
+                Opts::new(
+                    "probe_ping_rtt_seconds",
+                    "Probe: Round trip time of pings from the dish to the target.",
+                ),
+                &["target"],
+            )?,
+            drop_rate: GaugeVec::new(
+                Opts::new(
+                    "probe_ping_drop_rate",
+                    "Probe: Fraction of pings from the dish to the target dropped.",
+                ),
+                &["target"],
+            )?,
+            timestamp_seconds: GaugeVec::new(
+                Opts::new(
+                    "probe_ping_timestamp_seconds",
+                    "Probe: Unix time of the last successful ping of the target.",
+                ),
+                &["target"],
+            )?,
+        };
+
+        Ok(probe)
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<(), Error> {
+        registry.register(Box::new(self.rtt_seconds.clone()))?;
+        registry.register(Box::new(self.drop_rate.clone()))?;
+        registry.register(Box::new(self.timestamp_seconds.clone()))?;
+
+        Ok(())
+    }
+
+    /// Pings all targets every `interval` until shutdown.
+    pub async fn run(self, interval: Duration, mut shutdown: Shutdown) {
+        let mut interval = time::interval(interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {},
+                _ = shutdown.triggered() => return,
+            }
+
+            for target in &self.targets {
+                if let Err(e) = self.ping(target).await {
+                    error!("pinging {} from Starlink device: {}", target, e);
+                    self.exporter_metrics.observe_error(&e);
+                }
+            }
+        }
+    }
+
+    async fn ping(&self, target: &str) -> Result<(), Error> {
+        let result = match self.dish.ping_host(target.to_string()).await? {
+            Some(result) => result,
+            None => return Ok(()),
+        };
+        debug!("pinged {}: {:?}", target, &result);
+
+        if let Some(latency_ms) = result.latency_ms {
+            self.rtt_seconds
+                .get_metric_with_label_values(&[target])?
+                .set(latency_ms as f64 / 1000_f64);
+        }
+        if let Some(drop_rate) = result.drop_rate {
+            self.drop_rate
+                .get_metric_with_label_values(&[target])?
+                .set(drop_rate as f64);
+        }
+        self.timestamp_seconds
+            .get_metric_with_label_values(&[target])?
+            .set(unix_time_f64());
+
+        Ok(())
+    }
+}
+
+impl SpeedTestProbe {
+    pub fn new(dish: Dish, exporter_metrics: ExporterMetrics) -> Result<Self, Error> {
+        let probe = SpeedTestProbe {
+            dish: dish.with_timeout(SPEED_TEST_TIMEOUT),
+            exporter_metrics,
+
+            download_bps: Gauge::with_opts(Opts::new(
+                "probe_speed_test_download_bps",
+                "Probe: Download throughput of the last speed test in bits per second.",
+            ))?,
+            upload_bps: Gauge::with_opts(Opts::new(
+                "probe_speed_test_upload_bps",
+                "Probe: Upload throughput of the last speed test in bits per second.",
+            ))?,
+            latency_seconds: Gauge::with_opts(Opts::new(
+                "probe_speed_test_latency_seconds",
+                "Probe: Latency measured by the last speed test.",
+            ))?,
+            timestamp_seconds: Gauge::with_opts(Opts::new(
+                "probe_speed_test_timestamp_seconds",
+                "Probe: Unix time of the last successful speed test.",
+            ))?,
+        };
+
+        Ok(probe)
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<(), Error> {
+        registry.register(Box::new(self.download_bps.clone()))?;
+        registry.register(Box::new(self.upload_bps.clone()))?;
+        registry.register(Box::new(self.latency_seconds.clone()))?;
+        registry.register(Box::new(self.timestamp_seconds.clone()))?;
+
+        Ok(())
+    }
+
+    /// Runs a speed test at every time matching `schedule` until shutdown.
+    pub async fn run(self, schedule: Schedule, mut shutdown: Shutdown) {
+        while schedule.tick("speed test", &mut shutdown).await {
+            if let Err(e) = self.speed_test().await {
+                error!("running speed test on Starlink device: {}", e);
+                self.exporter_metrics.observe_error(&e);
+            }
+        }
+    }
+
+    async fn speed_test(&self) -> Result<(), Error> {
+        let result = match self.dish.speed_test().await? {
+            Some(result) => result,
+            None => return Ok(()),
+        };
+        debug!("ran speed test: {:?}", &result);
+
+        if let Some(download_bps) = result.download_bps {
+            self.download_bps.set(download_bps as f64);
+        }
+        if let Some(upload_bps) = result.upload_bps {
+            self.upload_bps.set(upload_bps as f64);
+        }
+        if let Some(latency_s) = result.latency_s {
+            self.latency_seconds.set(latency_s as f64);
+        }
+        self.timestamp_seconds.set(unix_time_f64());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlink::proto::space_x::api::device::{request, response, PingHostResponse, PingResult, SpeedTestResponse};
+
+    use crate::mock_dish;
+
+    #[tokio::test]
+    async fn pings_target() {
+        let (dish, requests) = mock_dish::serve(|_| {
+            response::Response::PingHost(PingHostResponse {
+                result: Some(PingResult {
+                    drop_rate: Some(0.25),
+                    latency_ms: Some(42_f32),
+                    ..Default::default()
+                }),
+            })
+        })
+        .await;
+        let probe = PingProbe::new(dish, vec![], ExporterMetrics::new().unwrap()).unwrap();
+
+        probe.ping("1.1.1.1").await.unwrap();
+
+        assert!(matches!(
+            &requests.lock().unwrap()[..],
+            [r] if matches!(&r.request, Some(request::Request::PingHost(p)) if p.address.as_deref() == Some("1.1.1.1"))
+        ));
+        assert_eq!(probe.rtt_seconds.with_label_values(&["1.1.1.1"]).get(), 0.042);
+        assert_eq!(probe.drop_rate.with_label_values(&["1.1.1.1"]).get(), 0.25);
+        assert!(probe.timestamp_seconds.with_label_values(&["1.1.1.1"]).get() > 0_f64);
+    }
+
+    #[tokio::test]
+    async fn runs_speed_test() {
+        let (dish, requests) = mock_dish::serve(|_| {
+            response::Response::SpeedTest(SpeedTestResponse {
+                download_bps: Some(100e6),
+                upload_bps: Some(10e6),
+                latency_s: Some(0.03),
+                ..Default::default()
+            })
+        })
+        .await;
+        let probe = SpeedTestProbe::new(dish, ExporterMetrics::new().unwrap()).unwrap();
+
+        probe.speed_test().await.unwrap();
+
+        assert!(matches!(
+            &requests.lock().unwrap()[..],
+            [r] if matches!(r.request, Some(request::Request::SpeedTest(_)))
+        ));
+        assert_eq!(probe.download_bps.get(), 100e6);
+        assert_eq!(probe.upload_bps.get(), 10e6);
+        assert_eq!(probe.latency_seconds.get(), 0.03_f32 as f64);
+        assert!(probe.timestamp_seconds.get() > 0_f64);
+    }
+}
@@ -0,0 +1,166 @@
+use chrono::Local;
+use cron::error::ErrorKind;
+use std::str::FromStr;
+use tokio::time;
+use tracing::debug;
+
+use crate::{
+    admin::{Action, Admin},
+    shutdown::Shutdown,
+};
+
+/// Index of the day of week field, once seconds are prefixed.
+const DAY_OF_WEEK: usize = 5;
+
+/// Cron expression evaluated in the local time zone. Takes the five fields of crontab, or six with leading seconds.
+/// Days of the week are numbered like in crontab, from `0` or `7` for Sunday to `6` for Saturday.
+#[derive(Debug, Clone)]
+pub struct Schedule(cron::Schedule);
+
+impl FromStr for Schedule {
+    type Err = cron::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // the `cron` crate expects seconds, crontab has minute precision
+        let mut fields = s.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+        if fields.len() == 5 {
+            fields.insert(0, "0".to_string());
+        }
+        if let Some(day_of_week) = fields.get_mut(DAY_OF_WEEK) {
+            *day_of_week = translate_day_of_week(day_of_week)?;
+        }
+
+        Ok(Schedule(fields.join(" ").parse()?))
+    }
+}
+
+/// Translates the numeric days of week of crontab to those of the `cron` crate, which numbers them from `1` for
+/// Sunday to `7` for Saturday. Names and `*` are the same in both and kept.
+fn translate_day_of_week(field: &str) -> Result<String, cron::error::Error> {
+    let invalid = || cron::error::Error::from(ErrorKind::Expression(format!("invalid day of week {}", field)));
+
+    let mut elements = vec![];
+    for element in field.split(',') {
+        let (range, step) = match element.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<usize>().map_err(|_| invalid())?)),
+            None => (element, None),
+        };
+        if !range.chars().all(|c| c.is_ascii_digit() || c == '-') {
+            elements.push(element.to_string());
+            continue;
+        }
+
+        let parse = |day: &str| day.parse::<usize>().ok().filter(|d| *d <= 7).ok_or_else(invalid);
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (parse(first)?, parse(last)?),
+            // `<day>/<step>` runs until the end of the week
+            None if step.is_some() => (parse(range)?, 7),
+            None => (parse(range)?, parse(range)?),
+        };
+        if first > last || step == Some(0) {
+            return Err(invalid());
+        }
+
+        // ranges ending on `7` wrap around to Sunday
+        let mut days = (first..=last)
+            .step_by(step.unwrap_or(1))
+            .map(|d| d % 7 + 1)
+            .collect::<Vec<_>>();
+        days.sort_unstable();
+        days.dedup();
+        elements.extend(days.iter().map(usize::to_string));
+    }
+
+    Ok(elements.join(","))
+}
+
+impl Schedule {
+    /// Waits until the next time matching the schedule. Returns `false` if there is none or on shutdown.
+    pub async fn tick(&self, what: &str, shutdown: &mut Shutdown) -> bool {
+        let next = match self.0.upcoming(Local).next() {
+            Some(next) => next,
+            None => return false,
+        };
+        debug!("scheduled {} at {}", what, &next);
+
+        let delay = (next - Local::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = time::sleep(delay) => true,
+            _ = shutdown.triggered() => false,
+        }
+    }
+}
+
+/// Performs `action` at every time matching `schedule` until shutdown.
+pub async fn run(admin: Admin, action: Action, schedule: Schedule, mut shutdown: Shutdown) {
+    while schedule.tick(action.as_str(), &mut shutdown).await {
+        // failures are logged by `perform`, the next time of the schedule is tried regardless
+        let _ = admin.perform(action, "schedule").await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, NaiveDate, TimeZone, Utc, Weekday};
+
+    /// Weekdays of the first week of matches after Monday, 2024-01-01.
+    fn weekdays(expression: &str) -> Vec<Weekday> {
+        let schedule = expression.parse::<Schedule>().unwrap();
+        let start = Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(2024, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+        );
+
+        let mut weekdays = schedule
+            .0
+            .after(&start)
+            .take_while(|t| *t < start + chrono::Duration::days(7))
+            .map(|t| t.weekday())
+            .collect::<Vec<_>>();
+        weekdays.sort_by_key(|d| d.num_days_from_monday());
+
+        weekdays
+    }
+
+    #[test]
+    fn monday_to_friday() {
+        assert_eq!(weekdays("0 22 * * 1-5"), [
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri
+        ]);
+    }
+
+    #[test]
+    fn sunday() {
+        assert_eq!(weekdays("0 22 * * 0"), [Weekday::Sun]);
+        assert_eq!(weekdays("0 22 * * 7"), [Weekday::Sun]);
+    }
+
+    #[test]
+    fn weekend() {
+        assert_eq!(weekdays("0 22 * * 6-7"), [Weekday::Sat, Weekday::Sun]);
+        assert_eq!(weekdays("0 0 22 * * 0,6"), [Weekday::Sat, Weekday::Sun]);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(weekdays("0 22 * * Mon-Wed"), [Weekday::Mon, Weekday::Tue, Weekday::Wed]);
+    }
+
+    #[test]
+    fn steps() {
+        assert_eq!(weekdays("0 22 * * 1-5/2"), [Weekday::Mon, Weekday::Wed, Weekday::Fri]);
+    }
+
+    #[test]
+    fn rejects_invalid_days() {
+        assert!("0 22 * * 8".parse::<Schedule>().is_err());
+        assert!("0 22 * * 5-1".parse::<Schedule>().is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use futures_util::future;
+use std::{future::Future, time::Duration};
+use tokio::{
+    signal::{self, unix::SignalKind},
+    sync::{mpsc, watch},
+    time,
+};
+use tracing::{info, warn};
+
+use crate::exporter_error::Error;
+
+/// Creates a `Trigger` and the first of the `Shutdown` handles it notifies.
+pub fn channel() -> (Trigger, Shutdown) {
+    let (notify_tx, notify_rx) = watch::channel(false);
+    let (done_tx, done_rx) = mpsc::channel(1);
+
+    (
+        Trigger {
+            notify: notify_tx,
+            done: done_rx,
+        },
+        Shutdown {
+            notify: notify_rx,
+            _done: done_tx,
+        },
+    )
+}
+
+#[derive(Debug)]
+pub struct Trigger {
+    notify: watch::Sender<bool>,
+    done: mpsc::Receiver<()>,
+}
+
+impl Trigger {
+    /// Notifies all `Shutdown` handles and waits until every one of them is dropped.
+    pub async fn shutdown(mut self) {
+        let _ = self.notify.send(true);
+        // only returns once all senders, held by the `Shutdown` handles, are dropped
+        let _ = self.done.recv().await;
+    }
+}
+
+/// Handle of a long-running task to shut down gracefully. The `Trigger` waits for all clones to be dropped, so tasks
+/// with work left to flush on shutdown hold on to theirs until they're done.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    notify: watch::Receiver<bool>,
+    _done: mpsc::Sender<()>,
+}
+
+impl Shutdown {
+    /// Completes once shutdown was triggered.
+    pub async fn triggered(&mut self) {
+        while !*self.notify.borrow() {
+            if self.notify.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Like `triggered`, but consuming the handle, e.g. to pass it as the shutdown signal of the server.
+    pub async fn wait(mut self) { self.triggered().await }
+}
+
+/// Runs `server` until SIGTERM or SIGINT, then gives in-flight requests and the tasks holding a `Shutdown` handle up to
+/// `grace_period` to finish.
+pub async fn run_until_signal(
+    server: impl Future<Output = ()>,
+    trigger: Trigger,
+    grace_period: Duration,
+) -> Result<(), Error> {
+    tokio::pin!(server);
+
+    let mut terminate = signal::unix::signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = &mut server => return Ok(()),
+        _ = terminate.recv() => info!("received SIGTERM"),
+        result = signal::ctrl_c() => {
+            result?;
+            info!("received SIGINT");
+        },
+    }
+
+    info!("shutting down gracefully, waiting up to {:?}", &grace_period);
+
+    if time::timeout(grace_period, future::join(server, trigger.shutdown()))
+        .await
+        .is_err()
+    {
+        warn!("grace period elapsed, shutting down forcefully");
+    }
+
+    Ok(())
+}
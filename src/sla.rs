@@ -0,0 +1,409 @@
+use futures_util::{Stream, StreamExt};
+use prometheus::{GaugeVec, Opts, Registry};
+use starlink_exporter::{
+    status::{State, Status},
+    util::unix_time,
+};
+use std::collections::VecDeque;
+
+use crate::{events::Event, exporter_error::Error, history::HistorySample};
+
+/// Windows the metrics are computed over, with their `window` label.
+const WINDOWS: &[(&str, usize)] = &[
+    ("1h", 60 * 60),
+    ("24h", 24 * 60 * 60),
+    ("7d", 7 * 24 * 60 * 60),
+    ("30d", 30 * 24 * 60 * 60),
+];
+
+/// Longest time between polls attributed to the status of the later one. Longer gaps are counted as unobserved, or
+/// without state when using the history.
+const MAX_GAP_S: i64 = 5 * 60;
+
+// flags of a second
+const OBSERVED: u8 = 1 << 0;
+const AVAILABLE: u8 = 1 << 1;
+const CONNECTED: u8 = 1 << 2;
+const SEARCHING: u8 = 1 << 3;
+const BOOTING: u8 = 1 << 4;
+const OBSTRUCTED: u8 = 1 << 5;
+/// First unavailable second after an available one.
+const OUTAGE: u8 = 1 << 6;
+
+/// Availability and time in state of the dish over sliding windows, tracked per second.
+///
+/// A second is available if not all pings to the PoP were dropped, and without the history also only if the dish was
+/// connected. With the history of the dish, drop rate and obstruction of every second are taken from its per-second
+/// samples, otherwise the status of a poll is attributed to the seconds since the previous one. The state of the dish
+/// is only polled, so it's attributed to the seconds since the previous poll either way.
+#[derive(Debug)]
+pub struct Sla {
+    /// Whether to account the history samples published by the poller. Only polled state is used otherwise.
+    history: bool,
+
+    /// Flags of every second of the longest window, the last one being `last_time`.
+    seconds: VecDeque<u8>,
+    last_time: i64,
+    last_poll: Option<i64>,
+    /// Status of the latest poll, until its history samples are accounted.
+    pending: Option<Status>,
+    /// Count of seconds with each flag set, per window.
+    counts: Vec<[u64; 8]>,
+
+    availability_ratio: GaugeVec,
+    observed_seconds: GaugeVec,
+    state_seconds: GaugeVec,
+    outages: GaugeVec,
+    mean_time_between_outages_seconds: GaugeVec,
+}
+
+impl Sla {
+    pub fn new(history: bool) -> Result<Self, Error> {
+        let sla = Sla {
+            history,
+
+            seconds: VecDeque::new(),
+            last_time: 0,
+            last_poll: None,
+            pending: None,
+            counts: vec![[0; 8]; WINDOWS.len()],
+
+            availability_ratio: GaugeVec::new(
+                Opts::new(
+                    "sla_availability_ratio",
+                    "SLA: Fraction of the observed seconds of the window the dish was available.",
+                ),
+                &["window"],
+            )?,
+            observed_seconds: GaugeVec::new(
+                Opts::new(
+                    "sla_observed_seconds",
+                    "SLA: Seconds of the window observed by the exporter, the base of the other SLA metrics.",
+                ),
+                &["window"],
+            )?,
+            state_seconds: GaugeVec::new(
+                Opts::new(
+                    "sla_state_seconds",
+                    "SLA: Seconds of the window the dish spent in the state. Obstructed seconds overlap the others.",
+                ),
+                &["window", "state"],
+            )?,
+            outages: GaugeVec::new(Opts::new("sla_outages", "SLA: Outages starting within the window."), &[
+                "window",
+            ])?,
+            mean_time_between_outages_seconds: GaugeVec::new(
+                Opts::new(
+                    "sla_mean_time_between_outages_seconds",
+                    "SLA: Available seconds of the window per outage. Absent without outages.",
+                ),
+                &["window"],
+            )?,
+        };
+
+        Ok(sla)
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<(), Error> {
+        registry.register(Box::new(self.availability_ratio.clone()))?;
+        registry.register(Box::new(self.observed_seconds.clone()))?;
+        registry.register(Box::new(self.state_seconds.clone()))?;
+        registry.register(Box::new(self.outages.clone()))?;
+        registry.register(Box::new(self.mean_time_between_outages_seconds.clone()))?;
+
+        Ok(())
+    }
+
+    /// Accounts the seconds since the previous poll on every poll until shutdown.
+    pub async fn run(mut self, events: impl Stream<Item = Event>) {
+        tokio::pin!(events);
+
+        while let Some(event) = events.next().await {
+            match event {
+                Event::Status(status) if self.history => self.pending = Some(status),
+                Event::Status(status) => {
+                    self.account(&status, None, unix_time());
+                    self.update_metrics();
+                },
+                Event::History(samples) =>
+                    if let Some(status) = self.pending.take() {
+                        self.account(&status, Some(samples), unix_time());
+                        self.update_metrics();
+                    },
+                _ => {},
+            }
+        }
+    }
+
+    /// Accounts the seconds of the history `samples`, or without them the seconds since the previous poll, for a poll
+    /// at `now` returning `status`.
+    fn account(&mut self, status: &Status, samples: Option<Vec<HistorySample>>, now: i64) {
+        let state = state_flags(status);
+        // the state is only known for the interval since the previous poll, not the whole backlog of the history
+        let polled = match self.last_poll {
+            Some(last_poll) => (last_poll + 1).max(now - MAX_GAP_S + 1),
+            None => now,
+        };
+        self.last_poll = Some(now);
+
+        match samples {
+            Some(samples) =>
+                for sample in samples {
+                    let state = if sample.time >= polled { state } else { 0 };
+
+                    self.push(
+                        sample.time,
+                        second_flags(state, sample.pop_ping_drop_rate, sample.obstructed),
+                    );
+                },
+            None => {
+                let mut flags = second_flags(
+                    state,
+                    status.pop_ping_drop_rate,
+                    status.obstruction.currently_obstructed,
+                );
+                if state & CONNECTED == 0 {
+                    flags &= !AVAILABLE;
+                }
+
+                for time in polled..=now {
+                    self.push(time, flags);
+                }
+            },
+        }
+    }
+
+    /// Appends the flags of the second at `time`, counting the seconds skipped since the last one as unobserved.
+    fn push(&mut self, time: i64, flags: u8) {
+        let longest = WINDOWS.iter().map(|(_, w)| *w).max().unwrap_or_default();
+
+        if !self.seconds.is_empty() && time <= self.last_time {
+            return;
+        }
+        if self.seconds.is_empty() || time - self.last_time > longest as i64 {
+            self.seconds.clear();
+            self.counts = vec![[0; 8]; WINDOWS.len()];
+            self.last_time = time - 1;
+        }
+
+        while self.last_time + 1 < time {
+            self.push_second(0, longest);
+        }
+
+        let mut flags = flags | OBSERVED;
+        let previous = self.seconds.back().copied().unwrap_or_default();
+        if flags & AVAILABLE == 0 && previous & (OBSERVED | AVAILABLE) == OBSERVED | AVAILABLE {
+            flags |= OUTAGE;
+        }
+        self.push_second(flags, longest);
+    }
+
+    fn push_second(&mut self, flags: u8, longest: usize) {
+        self.seconds.push_back(flags);
+        self.last_time += 1;
+
+        let len = self.seconds.len();
+        for ((_, window), counts) in WINDOWS.iter().zip(&mut self.counts) {
+            add(counts, flags, 1);
+            if len > *window {
+                add(counts, self.seconds[len - 1 - window], -1);
+            }
+        }
+
+        if len > longest {
+            self.seconds.pop_front();
+        }
+    }
+
+    fn update_metrics(&self) {
+        for ((window, _), counts) in WINDOWS.iter().zip(&self.counts) {
+            let count = |flag: u8| counts[flag.trailing_zeros() as usize] as f64;
+            let observed = count(OBSERVED);
+            let available = count(AVAILABLE);
+            let outages = count(OUTAGE);
+
+            self.observed_seconds.with_label_values(&[window]).set(observed);
+            if observed > 0_f64 {
+                self.availability_ratio
+                    .with_label_values(&[window])
+                    .set(available / observed);
+            }
+
+            let connected = count(CONNECTED);
+            let searching = count(SEARCHING);
+            let booting = count(BOOTING);
+            for (state, seconds) in [
+                ("connected", connected),
+                ("searching", searching),
+                ("booting", booting),
+                ("unknown", observed - connected - searching - booting),
+                ("obstructed", count(OBSTRUCTED)),
+            ] {
+                self.state_seconds.with_label_values(&[window, state]).set(seconds);
+            }
+
+            self.outages.with_label_values(&[window]).set(outages);
+            match outages > 0_f64 {
+                true => self
+                    .mean_time_between_outages_seconds
+                    .with_label_values(&[window])
+                    .set(available / outages),
+                // fails if it wasn't set before
+                false => {
+                    let _ = self.mean_time_between_outages_seconds.remove_label_values(&[window]);
+                },
+            }
+        }
+    }
+}
+
+/// Adds `n` to the counts of all flags set in `flags`.
+fn add(counts: &mut [u64; 8], flags: u8, n: i64) {
+    for (bit, count) in counts.iter_mut().enumerate() {
+        if flags & (1 << bit) != 0 {
+            *count = (*count as i64 + n) as u64;
+        }
+    }
+}
+
+/// Flags of a second in `state`, available unless all pings were dropped.
+fn second_flags(state: u8, drop_rate: Option<f32>, obstructed: Option<bool>) -> u8 {
+    let mut flags = state;
+    if obstructed == Some(true) {
+        flags |= OBSTRUCTED;
+    }
+    if matches!(drop_rate, Some(rate) if rate < 1_f32) {
+        flags |= AVAILABLE;
+    }
+
+    flags
+}
+
+fn state_flags(status: &Status) -> u8 {
+    match status.state {
+        Some(State::Connected) => CONNECTED,
+        Some(State::Searching) => SEARCHING,
+        Some(State::Booting) => BOOTING,
+        Some(State::Unknown) | None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn sla() -> Sla { Sla::new(false).unwrap() }
+
+    fn status(state: State, drop_rate: f32) -> Status {
+        Status {
+            state: Some(state),
+            pop_ping_drop_rate: Some(drop_rate),
+            ..Default::default()
+        }
+    }
+
+    fn sample(time: i64, drop_rate: f32) -> HistorySample {
+        HistorySample {
+            time,
+            pop_ping_drop_rate: Some(drop_rate),
+            pop_ping_latency_ms: None,
+            downlink_throughput_bps: None,
+            uplink_throughput_bps: None,
+            snr: None,
+            scheduled: None,
+            obstructed: None,
+        }
+    }
+
+    fn get(gauge: &GaugeVec, labels: &[&str]) -> f64 { gauge.with_label_values(labels).get() }
+
+    #[test]
+    fn computes_availability() {
+        let mut sla = sla();
+
+        for (time, flags) in [
+            (NOW, CONNECTED | AVAILABLE),
+            (NOW + 1, CONNECTED | AVAILABLE),
+            (NOW + 2, CONNECTED),
+            (NOW + 3, CONNECTED | AVAILABLE),
+            (NOW + 4, SEARCHING),
+            (NOW + 5, SEARCHING),
+        ] {
+            sla.push(time, flags);
+        }
+        sla.update_metrics();
+
+        assert_eq!(get(&sla.observed_seconds, &["1h"]), 6_f64);
+        assert_eq!(get(&sla.availability_ratio, &["1h"]), 0.5);
+        assert_eq!(get(&sla.state_seconds, &["1h", "connected"]), 4_f64);
+        assert_eq!(get(&sla.state_seconds, &["1h", "searching"]), 2_f64);
+        assert_eq!(get(&sla.outages, &["1h"]), 2_f64);
+        assert_eq!(get(&sla.mean_time_between_outages_seconds, &["1h"]), 1.5);
+    }
+
+    #[test]
+    fn slides_windows() {
+        let mut sla = sla();
+
+        sla.push(NOW, CONNECTED);
+        // skipped seconds are unobserved
+        sla.push(NOW + 60 * 60, CONNECTED | AVAILABLE);
+        sla.push(NOW + 60 * 60 + 1, CONNECTED | AVAILABLE);
+        sla.update_metrics();
+
+        // the first second left the 1h window, but not the longer ones
+        assert_eq!(get(&sla.observed_seconds, &["1h"]), 2_f64);
+        assert_eq!(get(&sla.availability_ratio, &["1h"]), 1_f64);
+        assert_eq!(get(&sla.observed_seconds, &["24h"]), 3_f64);
+        assert_eq!(get(&sla.availability_ratio, &["24h"]), 2_f64 / 3_f64);
+        assert_eq!(sla.seconds.len(), 60 * 60 + 2);
+
+        // gaps longer than the longest window start over
+        sla.push(NOW + 31 * 24 * 60 * 60, CONNECTED);
+        sla.update_metrics();
+
+        assert_eq!(get(&sla.observed_seconds, &["30d"]), 1_f64);
+        assert_eq!(sla.seconds.len(), 1);
+    }
+
+    #[test]
+    fn attributes_polls_to_interval() {
+        let mut sla = sla();
+
+        sla.account(&status(State::Connected, 0_f32), None, NOW);
+        sla.account(&status(State::Connected, 1_f32), None, NOW + 10);
+        // longer gaps are unobserved
+        sla.account(&status(State::Searching, 0_f32), None, NOW + 10 + MAX_GAP_S * 2);
+        sla.update_metrics();
+
+        assert_eq!(get(&sla.observed_seconds, &["1h"]), (1 + 10 + MAX_GAP_S) as f64);
+        assert_eq!(get(&sla.state_seconds, &["1h", "connected"]), 11_f64);
+        assert_eq!(get(&sla.state_seconds, &["1h", "searching"]), MAX_GAP_S as f64);
+        // only connected seconds are available without the history
+        assert_eq!(get(&sla.availability_ratio, &["1h"]), 1_f64 / (11 + MAX_GAP_S) as f64);
+    }
+
+    #[test]
+    fn takes_availability_from_history() {
+        let mut sla = sla();
+
+        // the first history holds a backlog from before the first poll
+        let samples = (NOW - 9..=NOW).map(|time| sample(time, 0_f32)).collect();
+        sla.account(&status(State::Searching, 1_f32), Some(samples), NOW);
+        let samples = (NOW + 1..=NOW + 10)
+            .map(|time| sample(time, if time > NOW + 5 { 1_f32 } else { 0_f32 }))
+            .collect();
+        sla.account(&status(State::Connected, 0_f32), Some(samples), NOW + 10);
+        sla.update_metrics();
+
+        assert_eq!(get(&sla.observed_seconds, &["1h"]), 20_f64);
+        assert_eq!(get(&sla.availability_ratio, &["1h"]), 15_f64 / 20_f64);
+        assert_eq!(get(&sla.outages, &["1h"]), 1_f64);
+        // the state of a poll isn't attributed to the backlog before it
+        assert_eq!(get(&sla.state_seconds, &["1h", "searching"]), 1_f64);
+        assert_eq!(get(&sla.state_seconds, &["1h", "connected"]), 10_f64);
+        assert_eq!(get(&sla.state_seconds, &["1h", "unknown"]), 9_f64);
+    }
+}
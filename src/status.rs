@@ -0,0 +1,193 @@
+use serde::Serialize;
+use std::sync::Arc;
+
+use starlink::proto::space_x::api::device::{DishAlerts, DishGetStatusResponse, DishObstructionStats, DishState};
+
+/// Snapshot of a single `GetStatusRequest` response, as pushed to stream subscribers.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Status {
+    pub software_version: Option<String>,
+    pub country_code: Option<String>,
+    pub uptime_s: Option<u64>,
+    pub state: Option<State>,
+    pub stow_requested: Option<bool>,
+    pub alerts: Alerts,
+    pub snr: Option<f32>,
+    pub seconds_to_first_nonempty_slot: Option<f32>,
+    pub pop_ping_drop_rate: Option<f32>,
+    pub downlink_throughput_bps: Option<f32>,
+    pub uplink_throughput_bps: Option<f32>,
+    pub pop_ping_latency_ms: Option<f32>,
+    pub obstruction: Obstruction,
+    /// The response the status was read from, for mappings of its raw fields like `DISH_METRICS`.
+    #[serde(skip)]
+    pub response: Arc<DishGetStatusResponse>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum State {
+    Unknown,
+    Connected,
+    Searching,
+    Booting,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Alerts {
+    pub motors_stuck: Option<bool>,
+    pub thermal_throttle: Option<bool>,
+    pub thermal_shutdown: Option<bool>,
+    pub mast_not_near_vertical: Option<bool>,
+    pub unexpected_location: Option<bool>,
+    pub slow_ethernet_speeds: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Obstruction {
+    pub currently_obstructed: Option<bool>,
+    pub fraction_obstructed: Option<f32>,
+    pub last_24h_obstructed_s: Option<f32>,
+    pub valid_s: Option<f32>,
+    pub wedge_fraction_obstructed: Vec<f32>,
+    pub wedge_abs_fraction_obstructed: Vec<f32>,
+}
+
+impl Status {
+    /// Whether the dish rebooted since `previous` was polled, i.e. its uptime decreased or it entered the booting
+    /// state.
+    pub fn rebooted_since(&self, previous: &Status) -> bool {
+        let uptime_decreased = matches!((previous.uptime_s, self.uptime_s), (Some(p), Some(c)) if c < p);
+        let started_booting = self.state == Some(State::Booting) && previous.state != Some(State::Booting);
+
+        uptime_decreased || started_booting
+    }
+}
+
+impl Alerts {
+    /// All alerts with their names as used in the `alert` metric subsystem.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, Option<bool>)> {
+        IntoIterator::into_iter([
+            ("motors_stuck", self.motors_stuck),
+            ("thermal_throttle", self.thermal_throttle),
+            ("thermal_shutdown", self.thermal_shutdown),
+            ("mast_not_near_vertical", self.mast_not_near_vertical),
+            ("unexpected_location", self.unexpected_location),
+            ("slow_ethernet_speeds", self.slow_ethernet_speeds),
+        ])
+    }
+}
+
+impl From<&DishGetStatusResponse> for Status {
+    fn from(response: &DishGetStatusResponse) -> Self {
+        let device_info = response.device_info.clone().unwrap_or_default();
+
+        Status {
+            software_version: device_info.software_version,
+            country_code: device_info.country_code,
+            uptime_s: response.device_state.as_ref().and_then(|s| s.uptime_s),
+            state: response.state.map(State::from),
+            stow_requested: response.stow_requested,
+            alerts: response.alerts.as_ref().map(Alerts::from).unwrap_or_default(),
+            snr: response.snr,
+            seconds_to_first_nonempty_slot: response.seconds_to_first_nonempty_slot,
+            pop_ping_drop_rate: response.pop_ping_drop_rate,
+            downlink_throughput_bps: response.downlink_throughput_bps,
+            uplink_throughput_bps: response.uplink_throughput_bps,
+            pop_ping_latency_ms: response.pop_ping_latency_ms,
+            obstruction: response
+                .obstruction_stats
+                .as_ref()
+                .map(Obstruction::from)
+                .unwrap_or_default(),
+            response: Arc::new(response.clone()),
+        }
+    }
+}
+
+impl From<i32> for State {
+    fn from(state: i32) -> Self {
+        match DishState::from_i32(state) {
+            Some(DishState::Connected) => State::Connected,
+            Some(DishState::Searching) => State::Searching,
+            Some(DishState::Booting) => State::Booting,
+            Some(DishState::Unknown) | None => State::Unknown,
+        }
+    }
+}
+
+impl From<&DishAlerts> for Alerts {
+    fn from(alerts: &DishAlerts) -> Self {
+        Alerts {
+            motors_stuck: alerts.motors_stuck,
+            thermal_throttle: alerts.thermal_throttle,
+            thermal_shutdown: alerts.thermal_shutdown,
+            mast_not_near_vertical: alerts.mast_not_near_vertical,
+            unexpected_location: alerts.unexpected_location,
+            slow_ethernet_speeds: alerts.slow_ethernet_speeds,
+        }
+    }
+}
+
+impl From<&DishObstructionStats> for Obstruction {
+    fn from(obstruction_stats: &DishObstructionStats) -> Self {
+        Obstruction {
+            currently_obstructed: obstruction_stats.currently_obstructed,
+            fraction_obstructed: obstruction_stats.fraction_obstructed,
+            last_24h_obstructed_s: obstruction_stats.last_24h_obstructed_s,
+            valid_s: obstruction_stats.valid_s,
+            wedge_fraction_obstructed: obstruction_stats.wedge_fraction_obstructed.clone(),
+            wedge_abs_fraction_obstructed: obstruction_stats.wedge_abs_fraction_obstructed.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(state: Option<State>, uptime_s: Option<u64>) -> Status {
+        Status {
+            state,
+            uptime_s,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn detects_reboots() {
+        let connected = Some(State::Connected);
+        let booting = Some(State::Booting);
+
+        for (previous, current, rebooted) in [
+            (status(connected, Some(100)), status(connected, Some(110)), false),
+            (status(connected, Some(100)), status(connected, Some(100)), false),
+            // the uptime decreased
+            (status(connected, Some(100)), status(connected, Some(5)), true),
+            (
+                status(connected, Some(100)),
+                status(Some(State::Searching), Some(99)),
+                true,
+            ),
+            // the dish started booting, even if its uptime wasn't reset yet
+            (status(connected, Some(100)), status(booting, Some(110)), true),
+            (status(connected, None), status(booting, None), true),
+            (status(None, Some(100)), status(booting, Some(110)), true),
+            // and stays booting
+            (status(booting, Some(5)), status(booting, Some(10)), false),
+            (status(booting, Some(10)), status(connected, Some(20)), false),
+            // missing uptimes aren't compared
+            (status(connected, Some(100)), status(connected, None), false),
+            (status(connected, None), status(connected, Some(5)), false),
+            (Status::default(), Status::default(), false),
+        ] {
+            assert_eq!(
+                current.rebooted_since(&previous),
+                rebooted,
+                "{:?} -> {:?}",
+                previous,
+                current
+            );
+        }
+    }
+}
@@ -0,0 +1,620 @@
+use futures_util::{Stream, StreamExt};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use starlink_exporter::{metrics::DISH_METRICS, status::Status, util::unix_time};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future,
+    io,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    task,
+    time::{self, MissedTickBehavior},
+};
+use tracing::{debug, error};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::{events::Event, exporter_error::Error, exporter_metrics::ExporterMetrics, history::HistorySample};
+
+/// Interval of downsampling and deleting expired samples.
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum number of points per series returned by a query.
+const MAX_POINTS: i64 = 11_000;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS series (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    labels TEXT NOT NULL,
+    UNIQUE (name, labels)
+);
+CREATE TABLE IF NOT EXISTS samples (
+    series_id INTEGER NOT NULL REFERENCES series (id),
+    timestamp INTEGER NOT NULL,
+    value REAL NOT NULL,
+    PRIMARY KEY (series_id, timestamp)
+) WITHOUT ROWID;
+CREATE TABLE IF NOT EXISTS downsampled (
+    series_id INTEGER NOT NULL REFERENCES series (id),
+    timestamp INTEGER NOT NULL,
+    value REAL NOT NULL,
+    PRIMARY KEY (series_id, timestamp)
+) WITHOUT ROWID;
+";
+
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    /// How long samples are kept at full resolution.
+    pub raw: Duration,
+    /// How long downsampled samples are kept.
+    pub downsampled: Duration,
+    /// Interval raw samples are averaged over when downsampling.
+    pub step: Duration,
+}
+
+/// SQLite database of the dish metrics of every poll and the per-second history of the dish.
+#[derive(Debug)]
+pub struct Storage {
+    connection: Mutex<Connection>,
+    retention: Retention,
+}
+
+/// Series as listed by `Storage::series`.
+#[derive(Debug)]
+pub struct StoredSeries {
+    pub id: i64,
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+struct Sample {
+    name: String,
+    /// Labels as JSON object.
+    labels: String,
+    timestamp: i64,
+    value: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    metric: String,
+    from: Option<i64>,
+    to: Option<i64>,
+    step: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct QueryResult {
+    metric: String,
+    series: Vec<Series>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Series {
+    labels: BTreeMap<String, String>,
+    /// Pairs of Unix time and value.
+    values: Vec<(i64, f64)>,
+}
+
+impl Storage {
+    pub fn open(path: &Path, retention: Retention) -> Result<Self, Error> {
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Storage {
+            connection: Mutex::new(connection),
+            retention,
+        })
+    }
+
+    /// Stores the dish metrics of the status of every poll and, with `history` set, the per-second samples the dish
+    /// recorded since the previous poll, as published by the poller. Downsamples and deletes expired samples once an
+    /// hour. Runs until the `events` end.
+    pub async fn run(
+        self: Arc<Self>,
+        events: impl Stream<Item = Event>,
+        history: bool,
+        exporter_metrics: ExporterMetrics,
+    ) {
+        let mut compaction = time::interval(COMPACTION_INTERVAL);
+        compaction.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        tokio::pin!(events);
+
+        loop {
+            let result = tokio::select! {
+                event = events.next() => match event {
+                    Some(Event::Status(status)) => self.record(status_samples(&status, unix_time())).await,
+                    Some(Event::PollFailed { .. }) => self.record(vec![up_sample(false, unix_time())]).await,
+                    Some(Event::History(samples)) if history => self.record(history_samples(samples)).await,
+                    Some(_) => Ok(()),
+                    None => return,
+                },
+                _ = compaction.tick() => {
+                    let storage = self.clone();
+                    blocking(move || storage.compact(unix_time())).await
+                },
+            };
+
+            if let Err(e) = result {
+                error!("storing metrics: {}", e);
+                exporter_metrics.observe_error(&e);
+            }
+        }
+    }
+
+    async fn record(self: &Arc<Self>, samples: Vec<Sample>) -> Result<(), Error> {
+        let storage = self.clone();
+        blocking(move || storage.insert(&samples)).await
+    }
+
+    fn insert(&self, samples: &[Sample]) -> Result<(), Error> {
+        let mut connection = self.connection.lock().expect("locking storage");
+        let transaction = connection.transaction()?;
+
+        {
+            let mut insert_series =
+                transaction.prepare_cached("INSERT OR IGNORE INTO series (name, labels) VALUES (?1, ?2)")?;
+            let mut select_series =
+                transaction.prepare_cached("SELECT id FROM series WHERE name = ?1 AND labels = ?2")?;
+            let mut insert_sample = transaction
+                .prepare_cached("INSERT OR REPLACE INTO samples (series_id, timestamp, value) VALUES (?1, ?2, ?3)")?;
+
+            let mut ids = HashMap::new();
+            for sample in samples {
+                let id = match ids.get(&(&sample.name, &sample.labels)) {
+                    Some(id) => *id,
+                    None => {
+                        insert_series.execute(params![sample.name, sample.labels])?;
+                        let id: i64 = select_series.query_row(params![sample.name, sample.labels], |row| row.get(0))?;
+                        ids.insert((&sample.name, &sample.labels), id);
+                        id
+                    },
+                };
+
+                insert_sample.execute(params![id, sample.timestamp, sample.value])?;
+            }
+        }
+
+        transaction.commit()?;
+        debug!("stored {} samples", samples.len());
+
+        Ok(())
+    }
+
+    /// Averages raw samples older than the raw retention per step and deletes downsampled samples older than their
+    /// retention.
+    fn compact(&self, now: i64) -> Result<(), Error> {
+        let step = self.retention.step.as_secs().max(1) as i64;
+        // aligned to the step, so every bucket is downsampled at once
+        let raw_cutoff = (now - self.retention.raw.as_secs() as i64) / step * step;
+        let cutoff = now - self.retention.downsampled.as_secs() as i64;
+
+        let mut connection = self.connection.lock().expect("locking storage");
+        let transaction = connection.transaction()?;
+
+        let downsampled = transaction.execute(
+            "INSERT OR REPLACE INTO downsampled (series_id, timestamp, value)
+             SELECT series_id, timestamp / ?1 * ?1 AS bucket, AVG(value) FROM samples
+             WHERE timestamp < ?2 GROUP BY series_id, bucket",
+            params![step, raw_cutoff],
+        )?;
+        let deleted = transaction.execute("DELETE FROM samples WHERE timestamp < ?1", params![raw_cutoff])?;
+        let expired = transaction.execute("DELETE FROM downsampled WHERE timestamp < ?1", params![cutoff])?;
+
+        transaction.commit()?;
+        debug!(
+            "downsampled {} samples into {} and deleted {} expired samples",
+            deleted, downsampled, expired
+        );
+
+        Ok(())
+    }
+
+    /// Series of `metric` between `from` and `to`, averaged per `step` seconds.
+    fn query(&self, metric: &str, from: i64, to: i64, step: i64) -> Result<Vec<Series>, Error> {
+        let connection = self.connection.lock().expect("locking storage");
+
+        let mut statement = connection.prepare_cached(
+            "SELECT s.labels, x.timestamp / ?4 * ?4 AS bucket, AVG(x.value)
+             FROM (
+                 SELECT series_id, timestamp, value FROM samples
+                 WHERE series_id IN (SELECT id FROM series WHERE name = ?1) AND timestamp BETWEEN ?2 AND ?3
+                 UNION ALL
+                 SELECT series_id, timestamp, value FROM downsampled
+                 WHERE series_id IN (SELECT id FROM series WHERE name = ?1) AND timestamp BETWEEN ?2 AND ?3
+             ) x
+             JOIN series s ON s.id = x.series_id
+             GROUP BY s.id, bucket
+             ORDER BY s.id, bucket",
+        )?;
+        let rows = statement.query_map(params![metric, from, to, step], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, f64>(2)?))
+        })?;
+
+        let mut series: Vec<Series> = vec![];
+        let mut last_labels = None;
+        for row in rows {
+            let (labels, timestamp, value) = row?;
+
+            if last_labels.as_ref() != Some(&labels) {
+                series.push(Series {
+                    labels: serde_json::from_str(&labels).unwrap_or_default(),
+                    values: vec![],
+                });
+                last_labels = Some(labels);
+            }
+            if let Some(series) = series.last_mut() {
+                series.values.push((timestamp, value));
+            }
+        }
+
+        Ok(series)
+    }
+
+    /// IDs, names and labels of the series with names matching the glob `pattern`.
+    pub fn series(&self, pattern: &str) -> Result<Vec<StoredSeries>, Error> {
+        let connection = self.connection.lock().expect("locking storage");
+
+        let mut statement = connection.prepare_cached("SELECT id, name, labels FROM series WHERE name GLOB ?1")?;
+        let rows = statement.query_map(params![pattern], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+
+        let mut series = vec![];
+        for row in rows {
+            let (id, name, labels) = row?;
+            series.push(StoredSeries {
+                id,
+                name,
+                labels: serde_json::from_str(&labels).unwrap_or_default(),
+            });
+        }
+
+        Ok(series)
+    }
+
+    /// Calls `f` with the time, series ID and value of every sample of the series with names matching the glob
+    /// `pattern` between `from` and `to`, ordered by time, until `f` returns `false`. Raw and downsampled samples are
+    /// scanned alike.
+    pub fn scan(
+        &self,
+        pattern: &str,
+        from: i64,
+        to: i64,
+        mut f: impl FnMut(i64, i64, f64) -> bool,
+    ) -> Result<(), Error> {
+        let connection = self.connection.lock().expect("locking storage");
+
+        let mut statement = connection.prepare_cached(
+            "SELECT timestamp, series_id, value FROM samples
+             WHERE series_id IN (SELECT id FROM series WHERE name GLOB ?1) AND timestamp BETWEEN ?2 AND ?3
+             UNION ALL
+             SELECT timestamp, series_id, value FROM downsampled
+             WHERE series_id IN (SELECT id FROM series WHERE name GLOB ?1) AND timestamp BETWEEN ?2 AND ?3
+             ORDER BY timestamp, series_id",
+        )?;
+        let mut rows = statement.query(params![pattern, from, to])?;
+        while let Some(row) = rows.next()? {
+            if !f(row.get(0)?, row.get(1)?, row.get(2)?) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `GET /api/v1/query?metric=&from=&to=&step=`, returning the stored series of `metric` between the Unix times `from`
+/// and `to` as JSON, averaged per `step` seconds. `to` defaults to now, `from` to an hour before `to` and `step` to
+/// `1`. Without storage, the route doesn't exist.
+pub fn route(storage: Option<Arc<Storage>>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("api" / "v1" / "query"))
+        .and_then(move || {
+            future::ready(match storage.clone() {
+                Some(storage) => Ok(storage),
+                None => Err(warp::reject::not_found()),
+            })
+        })
+        .and(warp::query::<Query>())
+        .and_then(|storage: Arc<Storage>, query: Query| async move {
+            let (from, to, step) = match range(&query, unix_time()) {
+                Ok(range) => range,
+                Err(message) => return Ok::<_, Rejection>(error_reply(StatusCode::BAD_REQUEST, message)),
+            };
+
+            let metric = query.metric.clone();
+            match blocking(move || storage.query(&metric, from, to, step)).await {
+                Ok(series) => Ok(warp::reply::with_status(
+                    warp::reply::json(&QueryResult {
+                        metric: query.metric,
+                        series,
+                    }),
+                    StatusCode::OK,
+                )),
+                Err(e) => {
+                    error!("querying storage: {}", e);
+
+                    Ok(error_reply(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+                },
+            }
+        })
+}
+
+/// Time range and step of `query`, or why they're invalid.
+fn range(query: &Query, now: i64) -> Result<(i64, i64, i64), String> {
+    let to = query.to.unwrap_or(now);
+    let from = match query.from {
+        Some(from) => from,
+        None => to.checked_sub(60 * 60).ok_or("invalid time range")?,
+    };
+    let step = query.step.unwrap_or(1);
+
+    let points = to
+        .checked_sub(from)
+        .filter(|span| *span >= 0 && step > 0)
+        .and_then(|span| span.checked_div(step))
+        .ok_or("invalid time range or step")?;
+    if points > MAX_POINTS {
+        return Err(format!("more than {} points per series, increase step", MAX_POINTS));
+    }
+
+    Ok((from, to, step))
+}
+
+fn error_reply(status: StatusCode, message: String) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(warp::reply::json(&BTreeMap::from([("error", message)])), status)
+}
+
+/// Values of the dish metrics in `status`, named as exported, along with `starlink_up`.
+fn status_samples(status: &Status, timestamp: i64) -> Vec<Sample> {
+    let mut samples = vec![up_sample(true, timestamp)];
+
+    for def in DISH_METRICS {
+        for (labels, value) in def.samples(&status.response) {
+            samples.push(Sample {
+                name: format!("starlink_dish_{}", def.name),
+                labels: serde_json::to_string(&labels.into_iter().collect::<BTreeMap<_, _>>())
+                    .expect("serializing labels"),
+                timestamp,
+                value,
+            });
+        }
+    }
+
+    // SQLite stores NaN as NULL
+    samples.retain(|s| s.value.is_finite());
+    samples
+}
+
+fn up_sample(up: bool, timestamp: i64) -> Sample {
+    Sample {
+        name: "starlink_up".to_string(),
+        labels: "{}".to_string(),
+        timestamp,
+        value: up as u8 as f64,
+    }
+}
+
+/// Samples of the values of the per-second history samples, named `starlink_history_*`.
+fn history_samples(history: Vec<HistorySample>) -> Vec<Sample> {
+    let mut samples = vec![];
+
+    for h in history {
+        let bool_to_f32 = |v: bool| v as u8 as f32;
+        let values = [
+            ("pop_ping_drop_rate", h.pop_ping_drop_rate),
+            ("pop_ping_latency_ms", h.pop_ping_latency_ms),
+            ("downlink_throughput_bps", h.downlink_throughput_bps),
+            ("uplink_throughput_bps", h.uplink_throughput_bps),
+            ("snr", h.snr),
+            ("scheduled", h.scheduled.map(bool_to_f32)),
+            ("obstructed", h.obstructed.map(bool_to_f32)),
+        ];
+
+        for (name, value) in values {
+            if let Some(value) = value {
+                samples.push(Sample {
+                    name: format!("starlink_history_{}", name),
+                    labels: "{}".to_string(),
+                    timestamp: h.time,
+                    value: value as f64,
+                });
+            }
+        }
+    }
+
+    samples.retain(|s| s.value.is_finite());
+    samples
+}
+
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T, Error> + Send + 'static) -> Result<T, Error> {
+    task::spawn_blocking(f).await.map_err(io::Error::from)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use starlink::proto::space_x::api::device::{DeviceInfo, DishGetStatusResponse, DishObstructionStats};
+
+    fn storage() -> Arc<Storage> {
+        let retention = Retention {
+            raw: Duration::from_secs(60),
+            downsampled: Duration::from_secs(120),
+            step: Duration::from_secs(60),
+        };
+
+        Arc::new(Storage::open(Path::new(":memory:"), retention).unwrap())
+    }
+
+    fn sample(timestamp: i64, value: f64) -> Sample {
+        Sample {
+            name: "starlink_dish_snr".to_string(),
+            labels: "{}".to_string(),
+            timestamp,
+            value,
+        }
+    }
+
+    fn values(storage: &Storage, metric: &str, from: i64, to: i64, step: i64) -> Vec<(i64, f64)> {
+        let series = storage.query(metric, from, to, step).unwrap();
+        assert_eq!(series.len(), 1);
+
+        series[0].values.clone()
+    }
+
+    #[test]
+    fn stores_status() {
+        let storage = storage();
+        let response = DishGetStatusResponse {
+            device_info: Some(DeviceInfo {
+                software_version: Some("1.2.3".to_string()),
+                ..Default::default()
+            }),
+            snr: Some(9_f32),
+            obstruction_stats: Some(DishObstructionStats {
+                wedge_fraction_obstructed: vec![0.5, 0.25],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        storage.insert(&status_samples(&Status::from(&response), 1000)).unwrap();
+
+        assert_eq!(values(&storage, "starlink_up", 0, 2000, 1), [(1000, 1_f64)]);
+        assert_eq!(values(&storage, "starlink_dish_snr", 0, 2000, 1), [(1000, 9_f64)]);
+        let wedges = storage
+            .query("starlink_dish_obstruction_wedge_fraction_obstructed", 0, 2000, 1)
+            .unwrap();
+        assert_eq!(wedges.len(), 2);
+        assert_eq!(wedges[1].labels.get("wedge").map(String::as_str), Some("1"));
+        assert_eq!(wedges[1].values, [(1000, 0.25)]);
+        let info = storage.query("starlink_dish_device_info", 0, 2000, 1).unwrap();
+        assert_eq!(
+            info[0].labels.get("software_version").map(String::as_str),
+            Some("1.2.3")
+        );
+        // not reported by the dish
+        assert!(storage
+            .query("starlink_dish_pop_ping_latency_ms", 0, 2000, 1)
+            .unwrap()
+            .is_empty());
+
+        storage.insert(&[up_sample(false, 1001)]).unwrap();
+        assert_eq!(values(&storage, "starlink_up", 0, 2000, 1), [
+            (1000, 1_f64),
+            (1001, 0_f64)
+        ]);
+    }
+
+    #[test]
+    fn averages_per_step() {
+        let storage = storage();
+        storage
+            .insert(&[
+                sample(0, 1_f64),
+                sample(10, 3_f64),
+                sample(60, 5_f64),
+                sample(70, 7_f64),
+            ])
+            .unwrap();
+
+        assert_eq!(values(&storage, "starlink_dish_snr", 0, 119, 60), [
+            (0, 2_f64),
+            (60, 6_f64)
+        ]);
+        assert_eq!(values(&storage, "starlink_dish_snr", 0, 119, 120), [(0, 4_f64)]);
+        assert_eq!(values(&storage, "starlink_dish_snr", 10, 60, 1), [
+            (10, 3_f64),
+            (60, 5_f64)
+        ]);
+        // samples of the same time are replaced
+        storage.insert(&[sample(70, 9_f64)]).unwrap();
+        assert_eq!(values(&storage, "starlink_dish_snr", 60, 119, 60), [(60, 7_f64)]);
+    }
+
+    #[test]
+    fn compacts_samples() {
+        let storage = storage();
+        storage
+            .insert(&[
+                sample(0, 1_f64),
+                sample(10, 3_f64),
+                sample(60, 5_f64),
+                sample(70, 7_f64),
+                sample(150, 9_f64),
+            ])
+            .unwrap();
+
+        // raw samples before 120 are downsampled, downsampled ones before 60 expire
+        storage.compact(180).unwrap();
+
+        assert_eq!(values(&storage, "starlink_dish_snr", 0, 300, 1), [
+            (60, 6_f64),
+            (150, 9_f64)
+        ]);
+        let raw: i64 = storage
+            .connection
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM samples", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(raw, 1);
+    }
+
+    async fn get(query: &str) -> StatusCode {
+        warp::test::request()
+            .path(&format!("/api/v1/query?metric=starlink_up&{}", query))
+            .reply(&route(Some(storage())))
+            .await
+            .status()
+    }
+
+    #[tokio::test]
+    async fn queries_range() {
+        assert_eq!(get("").await, StatusCode::OK);
+        assert_eq!(get("from=0&to=3600&step=60").await, StatusCode::OK);
+        assert_eq!(get("from=0&to=0").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_ranges() {
+        for query in [
+            "from=3600&to=0",
+            "step=0",
+            "step=-1",
+            "from=0&to=3600&step=0",
+            // overflowing
+            &format!("to={}", i64::MIN),
+            &format!("from={}&to={}", i64::MIN, i64::MAX),
+            &format!("from={}&to={}&step={}", i64::MIN, i64::MAX, i64::MAX),
+            // too many points
+            "from=0&to=3600000",
+        ] {
+            assert_eq!(get(query).await, StatusCode::BAD_REQUEST, "{}", query);
+        }
+    }
+
+    #[test]
+    fn defaults_range() {
+        let query = Query {
+            metric: "starlink_up".to_string(),
+            from: None,
+            to: None,
+            step: None,
+        };
+
+        assert_eq!(range(&query, 7200), Ok((3600, 7200, 1)));
+    }
+}
@@ -0,0 +1,91 @@
+use futures_util::{future, SinkExt, Stream, StreamExt};
+use std::{convert::Infallible, sync::Arc};
+use tracing::{debug, info};
+use warp::{
+    sse,
+    ws::{Message, WebSocket, Ws},
+    Filter,
+    Rejection,
+    Reply,
+};
+
+use crate::{events::Event, poller::Poller, shutdown::Shutdown};
+
+/// `GET /api/v1/stream`, serving dish events as WebSocket messages on upgrade requests and as Server-Sent Events
+/// otherwise. Streams end on shutdown.
+pub fn route(
+    poller: Arc<Poller>,
+    shutdown: Shutdown,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let poller = warp::any().map(move || poller.clone());
+    let shutdown = warp::any().map(move || shutdown.clone());
+
+    let websocket = warp::path!("api" / "v1" / "stream")
+        .and(warp::ws())
+        .and(poller.clone())
+        .and(shutdown.clone())
+        .map(|ws: Ws, poller: Arc<Poller>, shutdown: Shutdown| {
+            info!("incoming WebSocket stream subscription");
+
+            ws.on_upgrade(move |socket| websocket(socket, poller, shutdown))
+        });
+
+    let sse = warp::get()
+        .and(warp::path!("api" / "v1" / "stream"))
+        .and(poller)
+        .and(shutdown)
+        .and_then(|poller: Arc<Poller>, shutdown: Shutdown| async move {
+            info!("incoming SSE stream subscription");
+
+            let events = events(&poller, shutdown).await.map(|event| {
+                let data = serde_json::to_string(&event).expect("serializing event");
+                Ok::<_, Infallible>(sse::Event::default().event(event.name()).data(data))
+            });
+
+            Ok::<_, Rejection>(sse::reply(sse::keep_alive().stream(events)))
+        });
+
+    websocket.or(sse)
+}
+
+async fn websocket(socket: WebSocket, poller: Arc<Poller>, shutdown: Shutdown) {
+    let (mut tx, mut rx) = socket.split();
+    let events = events(&poller, shutdown).await;
+    tokio::pin!(events);
+
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let event = match event {
+                    Some(event) => event,
+                    None => break,
+                };
+                let data = serde_json::to_string(&event).expect("serializing event");
+                if tx.send(Message::text(data)).await.is_err() {
+                    break;
+                }
+            },
+            message = rx.next() => match message {
+                // incoming messages other than close frames are ignored
+                Some(Ok(message)) if !message.is_close() => {},
+                _ => break,
+            },
+        }
+    }
+
+    let _ = tx.close().await;
+
+    debug!("closed WebSocket stream subscription");
+}
+
+/// The latest known status, followed by all events published from now on. Events missed by lagging subscribers are
+/// skipped. Ends on shutdown.
+async fn events(poller: &Poller, shutdown: Shutdown) -> impl Stream<Item = Event> {
+    let events = poller.events("stream subscriber", shutdown);
+    let last_status = poller.last_status().await.map(Event::Status);
+
+    futures_util::stream::iter(last_status).chain(events.filter(|e| {
+        // the history of every poll would dwarf all other events
+        future::ready(!matches!(e, Event::History(_)))
+    }))
+}
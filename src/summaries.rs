@@ -0,0 +1,418 @@
+use futures_util::{Stream, StreamExt};
+use prometheus::{GaugeVec, Opts, Registry};
+use serde::Serialize;
+use sketches_ddsketch::{Config as SketchConfig, DDSketch};
+use starlink_exporter::util::unix_time;
+use std::{
+    collections::VecDeque,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use warp::{Filter, Rejection, Reply};
+
+use crate::{events::Event, exporter_error::Error};
+
+/// Number of slices a window is rolled over in. Samples leave a window a slice at a time.
+const SLICES: u64 = 60;
+
+/// Relative accuracy of the latency quantiles.
+const SKETCH_ACCURACY: f64 = 0.01;
+
+/// `quantile` labels of p50, p90 and p99.
+const QUANTILES: &[&str] = &["0.5", "0.9", "0.99"];
+
+/// Window of a summary, configured as a number followed by `s`, `m`, `h` or `d`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    /// As configured, used in the `window` label.
+    name: String,
+    duration: Duration,
+}
+
+impl FromStr for Window {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let units = [("s", 1), ("m", 60), ("h", 60 * 60), ("d", 24 * 60 * 60)];
+        let seconds = units
+            .iter()
+            .find_map(|(suffix, unit)| Some(s.strip_suffix(suffix)?.parse::<u64>().ok()? * unit));
+
+        match seconds {
+            Some(seconds) if seconds > 0 => Ok(Window {
+                name: s.to_string(),
+                duration: Duration::from_secs(seconds),
+            }),
+            _ => Err(Error::Config(format!("parsing summary window {}", s))),
+        }
+    }
+}
+
+/// Rolling p50/p90/p99/max of the PoP ping latency and mean PoP ping drop rate over windows, from the per-second
+/// history of the dish or, without it, from the polled values.
+#[derive(Debug)]
+pub struct Summaries {
+    /// Whether to add the history samples published by the poller. Only polled values are used otherwise.
+    history: bool,
+    inner: Mutex<Inner>,
+
+    samples: GaugeVec,
+    pop_ping_latency_ms: GaugeVec,
+    pop_ping_latency_max_ms: GaugeVec,
+    pop_ping_drop_rate_mean: GaugeVec,
+}
+
+#[derive(Debug)]
+struct Inner {
+    windows: Vec<Rolling>,
+}
+
+/// Samples of a window, kept per slice.
+#[derive(Debug)]
+struct Rolling {
+    window: Window,
+    slice_s: i64,
+    slices: VecDeque<Slice>,
+}
+
+struct Slice {
+    start: i64,
+    latency_ms: DDSketch,
+    drop_rate_sum: f64,
+    /// Samples with a drop rate, the base of its mean.
+    drop_rate_samples: u64,
+    samples: u64,
+}
+
+impl fmt::Debug for Slice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Slice")
+            .field("start", &self.start)
+            .field("latency_ms", &self.latency_ms.count())
+            .field("drop_rate_sum", &self.drop_rate_sum)
+            .field("drop_rate_samples", &self.drop_rate_samples)
+            .field("samples", &self.samples)
+            .finish()
+    }
+}
+
+/// Summary of a window, as returned by the JSON API.
+#[derive(Debug, Serialize)]
+struct Summary {
+    window: String,
+    samples: u64,
+    pop_ping_latency_ms: Option<LatencySummary>,
+    pop_ping_drop_rate_mean: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct LatencySummary {
+    p50: f64,
+    p90: f64,
+    p99: f64,
+    max: f64,
+}
+
+impl Summaries {
+    pub fn new(windows: Vec<Window>, history: bool) -> Result<Self, Error> {
+        let windows = windows
+            .into_iter()
+            .map(|window| Rolling {
+                slice_s: (window.duration.as_secs() / SLICES).max(1) as i64,
+                window,
+                slices: VecDeque::new(),
+            })
+            .collect();
+
+        let summaries = Summaries {
+            history,
+            inner: Mutex::new(Inner { windows }),
+
+            samples: GaugeVec::new(Opts::new("summary_samples", "Summary: Samples within the window."), &[
+                "window",
+            ])?,
+            pop_ping_latency_ms: GaugeVec::new(
+                Opts::new(
+                    "summary_pop_ping_latency_ms",
+                    "Summary: Quantile of the PoP ping latency in ms within the window, accurate to 1%.",
+                ),
+                &["window", "quantile"],
+            )?,
+            pop_ping_latency_max_ms: GaugeVec::new(
+                Opts::new(
+                    "summary_pop_ping_latency_max_ms",
+                    "Summary: Maximum PoP ping latency in ms within the window.",
+                ),
+                &["window"],
+            )?,
+            pop_ping_drop_rate_mean: GaugeVec::new(
+                Opts::new(
+                    "summary_pop_ping_drop_rate_mean",
+                    "Summary: Mean PoP ping drop rate within the window.",
+                ),
+                &["window"],
+            )?,
+        };
+
+        Ok(summaries)
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<(), Error> {
+        registry.register(Box::new(self.samples.clone()))?;
+        registry.register(Box::new(self.pop_ping_latency_ms.clone()))?;
+        registry.register(Box::new(self.pop_ping_latency_max_ms.clone()))?;
+        registry.register(Box::new(self.pop_ping_drop_rate_mean.clone()))?;
+
+        Ok(())
+    }
+
+    /// Adds the samples since the previous poll on every poll until shutdown.
+    pub async fn run(self: Arc<Self>, events: impl Stream<Item = Event>) {
+        tokio::pin!(events);
+
+        while let Some(event) = events.next().await {
+            let now = unix_time();
+            match event {
+                Event::Status(status) if !self.history =>
+                    self.add(&[(now, status.pop_ping_latency_ms, status.pop_ping_drop_rate)], now),
+                Event::History(samples) if self.history => {
+                    let samples = samples
+                        .iter()
+                        .map(|s| (s.time, s.pop_ping_latency_ms, s.pop_ping_drop_rate))
+                        .collect::<Vec<_>>();
+                    self.add(&samples, now);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Adds samples of time, latency and drop rate to every window and updates the metrics.
+    fn add(&self, samples: &[(i64, Option<f32>, Option<f32>)], now: i64) {
+        let mut inner = self.inner.lock().expect("locking summaries");
+        for rolling in &mut inner.windows {
+            for (time, latency_ms, drop_rate) in samples {
+                rolling.add(*time, *latency_ms, *drop_rate);
+            }
+        }
+
+        for summary in inner.summarize(now) {
+            self.update_metrics(&summary);
+        }
+    }
+
+    fn update_metrics(&self, summary: &Summary) {
+        let window = summary.window.as_str();
+
+        self.samples.with_label_values(&[window]).set(summary.samples as f64);
+        // the removals fail if the gauges weren't set before
+        match &summary.pop_ping_latency_ms {
+            Some(latency) => {
+                for (quantile, value) in QUANTILES.iter().zip([latency.p50, latency.p90, latency.p99]) {
+                    self.pop_ping_latency_ms
+                        .with_label_values(&[window, quantile])
+                        .set(value);
+                }
+                self.pop_ping_latency_max_ms
+                    .with_label_values(&[window])
+                    .set(latency.max);
+            },
+            None => {
+                for quantile in QUANTILES {
+                    let _ = self.pop_ping_latency_ms.remove_label_values(&[window, quantile]);
+                }
+                let _ = self.pop_ping_latency_max_ms.remove_label_values(&[window]);
+            },
+        }
+        match summary.pop_ping_drop_rate_mean {
+            Some(mean) => self.pop_ping_drop_rate_mean.with_label_values(&[window]).set(mean),
+            None => {
+                let _ = self.pop_ping_drop_rate_mean.remove_label_values(&[window]);
+            },
+        }
+    }
+}
+
+impl Inner {
+    fn summarize(&mut self, now: i64) -> Vec<Summary> {
+        self.windows.iter_mut().map(|rolling| rolling.summarize(now)).collect()
+    }
+}
+
+impl Rolling {
+    fn add(&mut self, time: i64, latency_ms: Option<f32>, drop_rate: Option<f32>) {
+        let start = time - time.rem_euclid(self.slice_s);
+        // samples are added in order, except for those older than the current slice after the clock changed
+        let slice = match self.slices.back_mut() {
+            Some(slice) if slice.start == start => slice,
+            Some(slice) if slice.start > start => return,
+            _ => {
+                self.slices.push_back(Slice {
+                    start,
+                    latency_ms: DDSketch::new(sketch_config()),
+                    drop_rate_sum: 0_f64,
+                    drop_rate_samples: 0,
+                    samples: 0,
+                });
+                self.slices.back_mut().expect("pushed slice")
+            },
+        };
+
+        slice.samples += 1;
+        if let Some(drop_rate) = drop_rate.filter(|r| r.is_finite()) {
+            slice.drop_rate_sum += drop_rate as f64;
+            slice.drop_rate_samples += 1;
+        }
+        // pings of seconds with all of them dropped have no latency
+        if let Some(latency_ms) = latency_ms.filter(|l| l.is_finite() && *l > 0_f32) {
+            if drop_rate.map(|r| r < 1_f32).unwrap_or(true) {
+                slice.latency_ms.add(latency_ms as f64);
+            }
+        }
+    }
+
+    /// Drops the slices that left the window and merges the others.
+    fn summarize(&mut self, now: i64) -> Summary {
+        let window_s = self.window.duration.as_secs() as i64;
+        while matches!(self.slices.front(), Some(slice) if slice.start + self.slice_s <= now - window_s) {
+            self.slices.pop_front();
+        }
+
+        let mut latency_ms = DDSketch::new(sketch_config());
+        let mut drop_rate_sum = 0_f64;
+        let mut drop_rate_samples = 0;
+        let mut samples = 0;
+        for slice in &self.slices {
+            // sketches of the same configuration always merge
+            let _ = latency_ms.merge(&slice.latency_ms);
+            drop_rate_sum += slice.drop_rate_sum;
+            drop_rate_samples += slice.drop_rate_samples;
+            samples += slice.samples;
+        }
+
+        // quantiles are only accurate to the relative accuracy, the maximum is exact
+        let quantile = |q: f64| Some(latency_ms.quantile(q).ok().flatten()?.min(latency_ms.max()?));
+        let latency = match (quantile(0.5), quantile(0.9), quantile(0.99), latency_ms.max()) {
+            (Some(p50), Some(p90), Some(p99), Some(max)) => Some(LatencySummary { p50, p90, p99, max }),
+            _ => None,
+        };
+
+        Summary {
+            window: self.window.name.clone(),
+            samples,
+            pop_ping_latency_ms: latency,
+            pop_ping_drop_rate_mean: (drop_rate_samples > 0).then(|| drop_rate_sum / drop_rate_samples as f64),
+        }
+    }
+}
+
+/// `GET /api/v1/summaries`, returning the summaries of all windows as JSON. Without summaries, the route doesn't
+/// exist.
+pub fn route(summaries: Option<Arc<Summaries>>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("api" / "v1" / "summaries"))
+        .and_then(move || {
+            let summaries = summaries.clone();
+
+            async move {
+                match summaries {
+                    Some(summaries) => {
+                        let summary = summaries
+                            .inner
+                            .lock()
+                            .expect("locking summaries")
+                            .summarize(unix_time());
+
+                        Ok(warp::reply::json(&summary))
+                    },
+                    None => Err(warp::reject::not_found()),
+                }
+            }
+        })
+}
+
+fn sketch_config() -> SketchConfig { SketchConfig::new(SKETCH_ACCURACY, 2048, 1.0e-9) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::core::Collector;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn summaries() -> Summaries { Summaries::new(vec!["1m".parse().unwrap()], false).unwrap() }
+
+    fn series(gauge_vec: &GaugeVec) -> usize { gauge_vec.collect()[0].get_metric().len() }
+
+    #[test]
+    fn averages_drop_rate_of_samples_reporting_it() {
+        let summaries = summaries();
+
+        summaries.add(
+            &[
+                (NOW, Some(20_f32), Some(0.5)),
+                (NOW + 1, Some(40_f32), None),
+                (NOW + 2, Some(30_f32), Some(f32::NAN)),
+                (NOW + 3, None, Some(0_f32)),
+            ],
+            NOW + 3,
+        );
+
+        assert_eq!(summaries.samples.with_label_values(&["1m"]).get(), 4_f64);
+        assert_eq!(summaries.pop_ping_drop_rate_mean.with_label_values(&["1m"]).get(), 0.25);
+        assert_eq!(
+            summaries.pop_ping_latency_max_ms.with_label_values(&["1m"]).get(),
+            40_f64
+        );
+    }
+
+    #[test]
+    fn summarizes_window() {
+        let mut rolling = Rolling {
+            window: "1m".parse().unwrap(),
+            slice_s: 1,
+            slices: VecDeque::new(),
+        };
+
+        for (i, latency_ms) in (1..=100).enumerate() {
+            rolling.add(NOW + i as i64 / 2, Some(latency_ms as f32), Some(0_f32));
+        }
+        // latency of seconds with all pings dropped is ignored
+        rolling.add(NOW + 50, Some(1000_f32), Some(1_f32));
+
+        let summary = rolling.summarize(NOW + 50);
+        let latency = summary.pop_ping_latency_ms.unwrap();
+        assert_eq!(summary.samples, 101);
+        assert!((latency.p50 - 50_f64).abs() <= 1_f64, "{}", latency.p50);
+        assert!(latency.p99 <= latency.max);
+        assert_eq!(latency.max, 100_f64);
+        assert_eq!(summary.pop_ping_drop_rate_mean, Some(1_f64 / 101_f64));
+
+        // samples leave the window a slice at a time
+        assert_eq!(rolling.summarize(NOW + 60).samples, 101);
+        assert_eq!(rolling.summarize(NOW + 61).samples, 101 - 2);
+        assert_eq!(rolling.summarize(NOW + 111).samples, 0);
+    }
+
+    #[test]
+    fn removes_gauges_without_samples() {
+        let summaries = summaries();
+
+        summaries.add(&[(NOW, Some(20_f32), Some(0_f32))], NOW);
+        assert_eq!(series(&summaries.pop_ping_latency_ms), QUANTILES.len());
+        assert_eq!(series(&summaries.pop_ping_latency_max_ms), 1);
+        assert_eq!(series(&summaries.pop_ping_drop_rate_mean), 1);
+
+        // only the drop rate is reported
+        summaries.add(&[(NOW + 120, None, Some(0_f32))], NOW + 120);
+        assert_eq!(series(&summaries.pop_ping_latency_ms), 0);
+        assert_eq!(series(&summaries.pop_ping_latency_max_ms), 0);
+        assert_eq!(series(&summaries.pop_ping_drop_rate_mean), 1);
+
+        summaries.add(&[], NOW + 240);
+        assert_eq!(series(&summaries.pop_ping_drop_rate_mean), 0);
+        assert_eq!(summaries.samples.with_label_values(&["1m"]).get(), 0_f64);
+    }
+}
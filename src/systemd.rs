@@ -0,0 +1,15 @@
+use sd_notify::NotifyState;
+use tracing::warn;
+
+/// Tells systemd that the exporter is serving. A no-op unless run as a `Type=notify` service.
+pub fn notify_ready() { notify(NotifyState::Ready); }
+
+/// Resets the systemd watchdog timer. Sent after every successful poll, so a hanging poll gets the exporter restarted
+/// if `WatchdogSec` is configured.
+pub fn notify_watchdog() { notify(NotifyState::Watchdog); }
+
+fn notify(state: NotifyState) {
+    if let Err(e) = sd_notify::notify(false, &[state]) {
+        warn!("notifying systemd: {}", e);
+    }
+}
@@ -0,0 +1,212 @@
+use futures_util::{Stream, StreamExt};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+    time,
+};
+use tokio_rustls::{
+    rustls::{
+        self,
+        server::{
+            AllowAnyAnonymousOrAuthenticatedClient,
+            AllowAnyAuthenticatedClient,
+            ClientHello,
+            NoClientAuth,
+            ResolvesServerCert,
+        },
+        sign::{self, CertifiedKey},
+        Certificate,
+        PrivateKey,
+        RootCertStore,
+        ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug, error, info};
+
+use crate::{
+    exporter_error::Error,
+    web_config::{ClientAuthType, TlsServerConfig, TlsVersion},
+};
+
+/// Time a client has to complete the TLS handshake, so idle connections don't hold on to their task.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builds the rustls server config. The certificate and key are reloaded on the next handshake after either file
+/// changed.
+pub fn server_config(config: &TlsServerConfig, http2: bool) -> Result<ServerConfig, Error> {
+    let client_cert_verifier = match (config.client_auth_type, &config.client_ca_file) {
+        (ClientAuthType::NoClientCert, _) => NoClientAuth::new(),
+        // exporter-toolkit accepts any certificate in these modes, rustls verifies every certificate it accepts
+        (client_auth_type @ (ClientAuthType::RequestClientCert | ClientAuthType::RequireAnyClientCert), _) =>
+            return Err(Error::Config(format!(
+                "client_auth_type {:?} isn't supported, as client certificates are always verified, use \
+                 VerifyClientCertIfGiven or RequireAndVerifyClientCert instead",
+                client_auth_type
+            ))),
+        (ClientAuthType::VerifyClientCertIfGiven, Some(client_ca_file)) =>
+            AllowAnyAnonymousOrAuthenticatedClient::new(load_roots(client_ca_file)?),
+        (ClientAuthType::RequireAndVerifyClientCert, Some(client_ca_file)) =>
+            AllowAnyAuthenticatedClient::new(load_roots(client_ca_file)?),
+        (client_auth_type, None) =>
+            return Err(Error::Config(format!(
+                "client_auth_type {:?} requires client_ca_file",
+                client_auth_type
+            ))),
+    };
+
+    let min_version = config.min_version.unwrap_or(TlsVersion::Tls12);
+    let max_version = config.max_version.unwrap_or(TlsVersion::Tls13);
+    let versions = [
+        (TlsVersion::Tls12, &rustls::version::TLS12),
+        (TlsVersion::Tls13, &rustls::version::TLS13),
+    ]
+    .iter()
+    .filter(|(v, _)| min_version <= *v && *v <= max_version)
+    .map(|(_, v)| *v)
+    .collect::<Vec<_>>();
+    if versions.is_empty() {
+        return Err(Error::Config(
+            "no supported TLS version between min_version and max_version".to_string(),
+        ));
+    }
+
+    let mut server_config = ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&versions)?
+        .with_client_cert_verifier(client_cert_verifier)
+        .with_cert_resolver(Arc::new(ReloadingCertResolver::new(
+            config.cert_file.clone(),
+            config.key_file.clone(),
+        )?));
+
+    server_config.alpn_protocols = match http2 {
+        true => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        false => vec![b"http/1.1".to_vec()],
+    };
+
+    Ok(server_config)
+}
+
+/// Performs the TLS handshakes of accepted connections concurrently, yielding established TLS streams. Failed
+/// handshakes, including those not completed within `HANDSHAKE_TIMEOUT`, are logged and dropped, so the stream never
+/// yields errors.
+pub fn incoming<S, C>(connections: S, config: ServerConfig) -> impl Stream<Item = io::Result<TlsStream<C>>>
+where
+    S: Stream<Item = C> + Send + 'static,
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let (tx, rx) = mpsc::channel(32);
+
+    tokio::spawn(async move {
+        tokio::pin!(connections);
+
+        while let Some(connection) = connections.next().await {
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(connection)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(Ok(stream)).await;
+                    },
+                    Ok(Err(e)) => debug!("TLS handshake failed: {}", e),
+                    Err(_) => debug!("TLS handshake timed out after {:?}", HANDSHAKE_TIMEOUT),
+                }
+            });
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+struct ReloadingCertResolver {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    current: Mutex<(Option<SystemTime>, Option<SystemTime>, Arc<CertifiedKey>)>,
+}
+
+impl ReloadingCertResolver {
+    fn new(cert_file: PathBuf, key_file: PathBuf) -> Result<Self, Error> {
+        let certified_key = load_certified_key(&cert_file, &key_file)?;
+
+        Ok(ReloadingCertResolver {
+            current: Mutex::new((modified(&cert_file), modified(&key_file), Arc::new(certified_key))),
+            cert_file,
+            key_file,
+        })
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let mut current = self.current.lock().expect("locking certificate");
+
+        let cert_modified = modified(&self.cert_file);
+        let key_modified = modified(&self.key_file);
+        if (cert_modified, key_modified) != (current.0, current.1) {
+            // keep serving the previous certificate if the new one can't be loaded, e.g. when only one of both files
+            // has been replaced yet
+            match load_certified_key(&self.cert_file, &self.key_file) {
+                Ok(certified_key) => {
+                    info!("reloaded TLS certificate from {}", self.cert_file.display());
+
+                    *current = (cert_modified, key_modified, Arc::new(certified_key));
+                },
+                Err(e) => error!("reloading TLS certificate: {}", e),
+            }
+        }
+
+        Some(current.2.clone())
+    }
+}
+
+fn load_certified_key(cert_file: &Path, key_file: &Path) -> Result<CertifiedKey, Error> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_file)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(Error::Config(format!(
+            "no certificate found in {}",
+            cert_file.display()
+        )));
+    }
+
+    let key = rustls_pemfile::read_all(&mut BufReader::new(File::open(key_file)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| Error::Config(format!("no private key found in {}", key_file.display())))?;
+    let key = sign::any_supported_type(&key)
+        .map_err(|_| Error::Config(format!("unsupported private key in {}", key_file.display())))?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+fn load_roots(client_ca_file: &Path) -> Result<RootCertStore, Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut BufReader::new(File::open(client_ca_file)?))? {
+        roots
+            .add(&Certificate(cert))
+            .map_err(|e| Error::Config(format!("invalid certificate in {}: {}", client_ca_file.display(), e)))?;
+    }
+
+    Ok(roots)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> { fs::metadata(path).and_then(|m| m.modified()).ok() }
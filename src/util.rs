@@ -0,0 +1,9 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Current Unix time in seconds, `0` if the clock is set before the epoch.
+pub fn unix_time() -> i64 { since_epoch().as_secs() as i64 }
+
+/// Current Unix time in seconds including fractions of a second, `0` if the clock is set before the epoch.
+pub fn unix_time_f64() -> f64 { since_epoch().as_secs_f64() }
+
+fn since_epoch() -> Duration { SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default() }
@@ -0,0 +1,271 @@
+use futures_util::{Stream, StreamExt};
+use starlink_exporter::status::{State, Status};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+use crate::{
+    admin::{Action, Admin},
+    events::Event,
+    exporter_metrics::ExporterMetrics,
+};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    NotConnected,
+    PingDropRate,
+    ThermalShutdown,
+}
+
+impl Condition {
+    /// Name of the condition as used in the `rule` label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Condition::NotConnected => "not_connected",
+            Condition::PingDropRate => "ping_drop_rate",
+            Condition::ThermalShutdown => "thermal_shutdown",
+        }
+    }
+
+    /// Whether the condition holds for `status`, or for a failed poll if `None`. A dish that can't be polled isn't
+    /// connected, but satisfies no other condition, just as fields missing from the status don't.
+    fn holds(&self, status: Option<&Status>) -> bool {
+        match (self, status) {
+            (Condition::NotConnected, None) => true,
+            (_, None) => false,
+            (Condition::NotConnected, Some(status)) => matches!(status.state, Some(state) if state != State::Connected),
+            (Condition::PingDropRate, Some(status)) => matches!(status.pop_ping_drop_rate, Some(rate) if rate >= 1_f32),
+            (Condition::ThermalShutdown, Some(status)) => status.alerts.thermal_shutdown == Some(true),
+        }
+    }
+}
+
+/// Fires once `condition` held on every poll for at least `duration`.
+#[derive(Debug, Clone, Copy)]
+pub struct Rule {
+    pub condition: Condition,
+    pub duration: Duration,
+}
+
+/// Reboots the dish when any of its rules fire, at most `max_reboots_per_day` times within 24 hours.
+#[derive(Debug)]
+pub struct Watchdog {
+    rules: Vec<(Rule, Option<Instant>)>,
+    max_reboots_per_day: usize,
+    dry_run: bool,
+    /// Times of the reboots within the last 24 hours, including those skipped in dry-run mode.
+    reboots: VecDeque<Instant>,
+    admin: Admin,
+    exporter_metrics: ExporterMetrics,
+}
+
+impl Watchdog {
+    pub fn new(
+        rules: Vec<Rule>,
+        max_reboots_per_day: usize,
+        dry_run: bool,
+        admin: Admin,
+        exporter_metrics: ExporterMetrics,
+    ) -> Self {
+        Watchdog {
+            rules: rules.into_iter().map(|r| (r, None)).collect(),
+            max_reboots_per_day,
+            dry_run,
+            reboots: VecDeque::new(),
+            admin,
+            exporter_metrics,
+        }
+    }
+
+    /// Evaluates the rules against the outcome of every poll until shutdown.
+    pub async fn run(mut self, events: impl Stream<Item = Event>) {
+        tokio::pin!(events);
+
+        while let Some(event) = events.next().await {
+            match event {
+                Event::Status(status) => self.observe(Some(&status), Instant::now()).await,
+                Event::PollFailed { .. } => self.observe(None, Instant::now()).await,
+                _ => {},
+            }
+        }
+    }
+
+    async fn observe(&mut self, status: Option<&Status>, now: Instant) {
+        let mut fired = None;
+        for (rule, since) in &mut self.rules {
+            match rule.condition.holds(status) {
+                true => {
+                    let held = now.duration_since(*since.get_or_insert(now));
+                    if fired.is_none() && held >= rule.duration {
+                        fired = Some((rule.condition, held));
+                    }
+                },
+                false => *since = None,
+            }
+        }
+
+        if let Some((condition, held)) = fired {
+            // a reboot addresses all conditions, which have to hold for their full duration again afterwards
+            for (_, since) in &mut self.rules {
+                *since = None;
+            }
+
+            self.reboot(condition, held, now).await;
+        }
+    }
+
+    async fn reboot(&mut self, condition: Condition, held: Duration, now: Instant) {
+        while matches!(self.reboots.front(), Some(reboot) if now.duration_since(*reboot) >= DAY) {
+            self.reboots.pop_front();
+        }
+
+        let rule = condition.as_str();
+        let held_s = held.as_secs();
+
+        let result = if self.reboots.len() >= self.max_reboots_per_day {
+            warn!(
+                rule,
+                held_s,
+                max_reboots_per_day = self.max_reboots_per_day,
+                "watchdog rule fired, but reboot limit reached"
+            );
+
+            "rate_limited"
+        } else {
+            self.reboots.push_back(now);
+
+            if self.dry_run {
+                warn!(rule, held_s, "watchdog rule fired, not rebooting in dry-run mode");
+
+                "dry_run"
+            } else {
+                warn!(rule, held_s, "watchdog rule fired, rebooting");
+
+                // the outcome is logged by `perform`
+                match self.admin.perform(Action::Reboot, "watchdog").await {
+                    Ok(()) => "rebooted",
+                    Err(_) => "failed",
+                }
+            }
+        };
+
+        self.exporter_metrics
+            .watchdog_triggers_total
+            .with_label_values(&[rule, result])
+            .inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlink_exporter::dish::Dish;
+    use tonic::transport::Channel;
+
+    fn watchdog(rules: &[(Condition, u64)], max_reboots_per_day: usize) -> Watchdog {
+        let exporter_metrics = ExporterMetrics::new().unwrap();
+        let dish = Dish::from_channel(Channel::from_static("http://127.0.0.1:9200").connect_lazy());
+        let rules = rules
+            .iter()
+            .map(|(condition, duration)| Rule {
+                condition: *condition,
+                duration: Duration::from_secs(*duration),
+            })
+            .collect();
+
+        Watchdog::new(
+            rules,
+            max_reboots_per_day,
+            true,
+            Admin::new(dish, exporter_metrics.clone()),
+            exporter_metrics,
+        )
+    }
+
+    fn status(state: State, drop_rate: f32) -> Status {
+        Status {
+            state: Some(state),
+            pop_ping_drop_rate: Some(drop_rate),
+            ..Default::default()
+        }
+    }
+
+    fn triggers(watchdog: &Watchdog, rule: &str, result: &str) -> f64 {
+        watchdog
+            .exporter_metrics
+            .watchdog_triggers_total
+            .with_label_values(&[rule, result])
+            .get()
+    }
+
+    #[tokio::test]
+    async fn fires_after_duration() {
+        let mut watchdog = watchdog(&[(Condition::NotConnected, 60)], 1);
+        let start = Instant::now();
+
+        watchdog.observe(Some(&status(State::Searching, 0_f32)), start).await;
+        watchdog
+            .observe(Some(&status(State::Searching, 0_f32)), start + Duration::from_secs(59))
+            .await;
+        assert_eq!(triggers(&watchdog, "not_connected", "dry_run"), 0_f64);
+
+        watchdog
+            .observe(Some(&status(State::Searching, 0_f32)), start + Duration::from_secs(60))
+            .await;
+        assert_eq!(triggers(&watchdog, "not_connected", "dry_run"), 1_f64);
+    }
+
+    #[tokio::test]
+    async fn starts_over_when_condition_stops() {
+        let mut watchdog = watchdog(&[(Condition::PingDropRate, 60)], 1);
+        let start = Instant::now();
+
+        watchdog.observe(Some(&status(State::Connected, 1_f32)), start).await;
+        watchdog
+            .observe(Some(&status(State::Connected, 0.5)), start + Duration::from_secs(30))
+            .await;
+        watchdog
+            .observe(Some(&status(State::Connected, 1_f32)), start + Duration::from_secs(60))
+            .await;
+        watchdog
+            .observe(Some(&status(State::Connected, 1_f32)), start + Duration::from_secs(119))
+            .await;
+        assert_eq!(triggers(&watchdog, "ping_drop_rate", "dry_run"), 0_f64);
+
+        watchdog
+            .observe(Some(&status(State::Connected, 1_f32)), start + Duration::from_secs(120))
+            .await;
+        assert_eq!(triggers(&watchdog, "ping_drop_rate", "dry_run"), 1_f64);
+    }
+
+    #[tokio::test]
+    async fn treats_failed_polls_as_not_connected() {
+        let mut watchdog = watchdog(&[(Condition::NotConnected, 60), (Condition::PingDropRate, 60)], 1);
+        let start = Instant::now();
+
+        watchdog.observe(Some(&status(State::Searching, 1_f32)), start).await;
+        watchdog.observe(None, start + Duration::from_secs(30)).await;
+        watchdog.observe(None, start + Duration::from_secs(60)).await;
+
+        assert_eq!(triggers(&watchdog, "not_connected", "dry_run"), 1_f64);
+        assert_eq!(triggers(&watchdog, "ping_drop_rate", "dry_run"), 0_f64);
+    }
+
+    #[tokio::test]
+    async fn limits_reboots_per_day() {
+        let mut watchdog = watchdog(&[(Condition::NotConnected, 0)], 1);
+        let start = Instant::now();
+
+        watchdog.observe(None, start).await;
+        watchdog.observe(None, start + Duration::from_secs(60)).await;
+        assert_eq!(triggers(&watchdog, "not_connected", "dry_run"), 1_f64);
+        assert_eq!(triggers(&watchdog, "not_connected", "rate_limited"), 1_f64);
+
+        watchdog.observe(None, start + DAY).await;
+        assert_eq!(triggers(&watchdog, "not_connected", "dry_run"), 2_f64);
+    }
+}
@@ -0,0 +1,150 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs,
+    path::{Path, PathBuf},
+};
+use warp::http::{header::HeaderName, HeaderMap, HeaderValue};
+
+use crate::exporter_error::Error;
+
+/// HTTP server configuration, compatible with the `web-config.yml` format of the Prometheus exporter-toolkit.
+///
+/// See <https://github.com/prometheus/exporter-toolkit/blob/master/docs/web-configuration.md>. Keys that aren't
+/// supported are rejected rather than ignored, as ignoring some of them, like `client_allowed_sans`, would accept more
+/// clients than configured.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebConfig {
+    pub tls_server_config: Option<TlsServerConfig>,
+    #[serde(default)]
+    pub http_server_config: HttpServerConfig,
+    /// Usernames mapped to bcrypt hashes of their passwords.
+    #[serde(default)]
+    pub basic_auth_users: HashMap<String, String>,
+    /// Tokens accepted as `Authorization: Bearer <token>`. Not part of the exporter-toolkit format.
+    #[serde(default)]
+    pub bearer_tokens: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsServerConfig {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    #[serde(default)]
+    pub client_auth_type: ClientAuthType,
+    pub client_ca_file: Option<PathBuf>,
+    pub min_version: Option<TlsVersion>,
+    pub max_version: Option<TlsVersion>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum ClientAuthType {
+    #[default]
+    NoClientCert,
+    RequestClientCert,
+    RequireAnyClientCert,
+    VerifyClientCertIfGiven,
+    RequireAndVerifyClientCert,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "TLS10")]
+    Tls10,
+    #[serde(rename = "TLS11")]
+    Tls11,
+    #[serde(rename = "TLS12")]
+    Tls12,
+    #[serde(rename = "TLS13")]
+    Tls13,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpServerConfig {
+    #[serde(default = "default_http2")]
+    pub http2: bool,
+    /// Headers added to every response.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+impl WebConfig {
+    /// Reads the config from a YAML file. Relative paths in the config are resolved against the directory of the file.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let mut config: WebConfig = serde_yaml::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| Error::Config(format!("invalid web config {}: {}", path.display(), e)))?;
+
+        if let (Some(tls_server_config), Some(dir)) = (config.tls_server_config.as_mut(), path.parent()) {
+            tls_server_config.cert_file = dir.join(&tls_server_config.cert_file);
+            tls_server_config.key_file = dir.join(&tls_server_config.key_file);
+            tls_server_config.client_ca_file = tls_server_config.client_ca_file.as_ref().map(|f| dir.join(f));
+        }
+
+        Ok(config)
+    }
+}
+
+impl HttpServerConfig {
+    pub fn header_map(&self) -> Result<HeaderMap, Error> {
+        let mut header_map = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name =
+                HeaderName::try_from(name).map_err(|_| Error::Config(format!("invalid header name {}", name)))?;
+            let value = HeaderValue::try_from(value)
+                .map_err(|_| Error::Config(format!("invalid value for header {}", name)))?;
+            header_map.insert(name, value);
+        }
+
+        Ok(header_map)
+    }
+}
+
+impl Default for HttpServerConfig {
+    fn default() -> Self {
+        HttpServerConfig {
+            http2: default_http2(),
+            headers: HashMap::new(),
+        }
+    }
+}
+
+fn default_http2() -> bool { true }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_config() {
+        let config: WebConfig = serde_yaml::from_str(
+            "tls_server_config:\n  cert_file: server.crt\n  key_file: server.key\n  client_auth_type: \
+             RequireAndVerifyClientCert\n  min_version: TLS12\nhttp_server_config:\n  http2: false\n",
+        )
+        .unwrap();
+
+        let tls_server_config = config.tls_server_config.unwrap();
+        assert_eq!(
+            tls_server_config.client_auth_type,
+            ClientAuthType::RequireAndVerifyClientCert
+        );
+        assert_eq!(tls_server_config.min_version, Some(TlsVersion::Tls12));
+        assert!(!config.http_server_config.http2);
+    }
+
+    #[test]
+    fn rejects_unsupported_keys() {
+        for yaml in [
+            "tls_server_config:\n  cert_file: a\n  key_file: b\n  client_allowed_sans: [client.example.com]\n",
+            "tls_server_config:\n  cert_file: a\n  key_file: b\n  cipher_suites: [TLS_AES_128_GCM_SHA256]\n",
+            "http_server_config:\n  http3: true\n",
+            "basic_auth_user:\n  prometheus: secret\n",
+        ] {
+            let result = serde_yaml::from_str::<WebConfig>(yaml);
+            assert!(result.is_err(), "{}", yaml);
+        }
+    }
+}